@@ -0,0 +1,170 @@
+//! A buffered reader that can be taken apart and put back together.
+//!
+//! The handshake is read through a buffer, and whatever was read past the end
+//! of the HTTP head belongs to the WebSocket connection. Unlike the standard
+//! library's `BufReader` this one hands its buffer out (`into_parts`) and can be
+//! rebuilt from one (`from_parts`) so those bytes are never lost.
+use std::cmp;
+use std::io::{self, Read, BufRead};
+
+/// The initial capacity of the buffer.
+pub const INIT_BUFFER_SIZE: usize = 4096;
+/// The largest the buffer will grow to while reading a message head.
+pub const MAX_BUFFER_SIZE: usize = 8192 + 4096 * 100;
+
+/// A `BufRead` wrapper around a reader, with access to its buffer.
+#[derive(Debug)]
+pub struct BufReader<R> {
+	inner: R,
+	buf: Vec<u8>,
+	pos: usize,
+	cap: usize,
+}
+
+impl<R> BufReader<R>
+	where R: Read
+{
+	/// Creates a new `BufReader` with a default capacity.
+	pub fn new(rdr: R) -> BufReader<R> {
+		BufReader::with_capacity(rdr, INIT_BUFFER_SIZE)
+	}
+
+	/// Creates a new `BufReader` with the given capacity.
+	pub fn with_capacity(rdr: R, cap: usize) -> BufReader<R> {
+		BufReader {
+			inner: rdr,
+			buf: vec![0; cap],
+			pos: 0,
+			cap: 0,
+		}
+	}
+
+	/// Rebuilds a `BufReader` from a reader and its buffer, the valid data
+	/// in the buffer is `&buf[pos..cap]`.
+	pub fn from_parts(rdr: R, buf: Vec<u8>, pos: usize, cap: usize) -> BufReader<R> {
+		BufReader {
+			inner: rdr,
+			buf: buf,
+			pos: pos,
+			cap: cap,
+		}
+	}
+
+	/// Gets a reference to the underlying reader.
+	pub fn get_ref(&self) -> &R {
+		&self.inner
+	}
+
+	/// Gets a mutable reference to the underlying reader.
+	pub fn get_mut(&mut self) -> &mut R {
+		&mut self.inner
+	}
+
+	/// The data that has been buffered but not read yet.
+	pub fn get_buf(&self) -> &[u8] {
+		&self.buf[self.pos..self.cap]
+	}
+
+	/// Takes the buffer out of the reader, leaving it with an empty one.
+	/// Returns the buffer, the position and the end of the valid data.
+	pub fn take_buf(&mut self) -> (Vec<u8>, usize, usize) {
+		let (pos, cap) = (self.pos, self.cap);
+		self.pos = 0;
+		self.cap = 0;
+		(::std::mem::replace(&mut self.buf, Vec::new()), pos, cap)
+	}
+
+	/// Unwraps this `BufReader`, returning the underlying reader.
+	/// Any buffered data is lost.
+	pub fn into_inner(self) -> R {
+		self.inner
+	}
+
+	/// Takes the reader apart into the underlying reader, the buffer, the
+	/// position and the end of the valid data.
+	pub fn into_parts(self) -> (R, Vec<u8>, usize, usize) {
+		(self.inner, self.buf, self.pos, self.cap)
+	}
+
+	/// Reads more data into the buffer, growing it if it is full,
+	/// returning how many bytes were read. Returns `Ok(0)` at the end of
+	/// the stream or when the buffer can't grow any bigger.
+	pub fn read_into_buf(&mut self) -> io::Result<usize> {
+		if self.pos > 0 {
+			// move the unread data to the front to make room
+			let len = self.cap - self.pos;
+			for i in 0..len {
+				self.buf[i] = self.buf[self.pos + i];
+			}
+			self.pos = 0;
+			self.cap = len;
+		}
+		if self.cap == self.buf.len() {
+			if self.buf.len() >= MAX_BUFFER_SIZE {
+				return Ok(0);
+			}
+			let len = cmp::min(cmp::max(self.buf.len() * 2, INIT_BUFFER_SIZE), MAX_BUFFER_SIZE);
+			self.buf.resize(len, 0);
+		}
+		let n = try!(self.inner.read(&mut self.buf[self.cap..]));
+		self.cap += n;
+		Ok(n)
+	}
+}
+
+impl<R> Read for BufReader<R>
+	where R: Read
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.cap == self.pos && buf.len() >= self.buf.len() {
+			return self.inner.read(buf);
+		}
+		let nread = {
+			let mut rem = try!(self.fill_buf());
+			try!(rem.read(buf))
+		};
+		self.consume(nread);
+		Ok(nread)
+	}
+}
+
+impl<R> BufRead for BufReader<R>
+	where R: Read
+{
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		if self.pos == self.cap {
+			if self.buf.is_empty() {
+				self.buf.resize(INIT_BUFFER_SIZE, 0);
+			}
+			self.cap = try!(self.inner.read(&mut self.buf));
+			self.pos = 0;
+		}
+		Ok(&self.buf[self.pos..self.cap])
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.pos = cmp::min(self.pos + amt, self.cap);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Read, BufRead};
+
+	#[test]
+	fn parts_keep_buffered_data() {
+		let mut reader = BufReader::with_capacity(Cursor::new(b"hello world".to_vec()), 4);
+		assert_eq!(reader.read_into_buf().unwrap(), 4);
+		// the full buffer grows to make room for the rest
+		assert_eq!(reader.read_into_buf().unwrap(), 7);
+		assert_eq!(reader.get_buf(), b"hello world");
+		reader.consume(6);
+
+		let (inner, buf, pos, cap) = reader.into_parts();
+		let mut reader = BufReader::from_parts(inner, buf, pos, cap);
+		let mut rest = String::new();
+		reader.read_to_string(&mut rest).unwrap();
+		assert_eq!(rest, "world");
+	}
+}
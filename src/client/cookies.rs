@@ -0,0 +1,338 @@
+//! A cookie jar for `ClientBuilder`, after RFC 6265.
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::{Url, Host};
+
+use header::Headers;
+
+/// Keeps the cookies servers set, to send them back with later upgrade
+/// requests. Clones of a jar share the cookies, so a jar given to a
+/// `ClientBuilder` keeps what it learned across reconnects and can be
+/// filled in beforehand, say with the session cookie of an HTTP login.
+///
+/// Cookies are matched against the WebSocket URL as if it were an HTTP
+/// one, `wss://` counting as secure. Public suffixes aren't known, so a
+/// cookie for a domain without a dot, like `com`, is refused but one for
+/// `co.uk` isn't.
+///
+/// ```rust,no_run
+/// # use websocket::ClientBuilder;
+/// use websocket::client::{CookieJar, Url};
+///
+/// let jar = CookieJar::new();
+/// let login = Url::parse("http://example.com/login").unwrap();
+/// // from the response of the login
+/// jar.set_cookie(&login, "session=31d4d96e407aad42; Path=/; HttpOnly");
+///
+/// let client = ClientBuilder::new("ws://example.com/chat").unwrap()
+///     .cookie_jar(jar.clone())
+///     .connect_insecure()
+///     .unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct CookieJar {
+	cookies: Arc<Mutex<Vec<StoredCookie>>>,
+}
+
+#[derive(Clone, Debug)]
+struct StoredCookie {
+	name: String,
+	value: String,
+	/// Lowercase, without a leading dot.
+	domain: String,
+	host_only: bool,
+	path: String,
+	secure: bool,
+	expires: Option<SystemTime>,
+}
+
+impl CookieJar {
+	/// An empty jar.
+	pub fn new() -> Self {
+		CookieJar::default()
+	}
+
+	/// Stores the cookie of a `Set-Cookie` header value a response to a
+	/// request for `url` came with. Returns whether the cookie was stored,
+	/// which it isn't if it's malformed or `url` may not set it. A cookie
+	/// that has expired removes the one it matches.
+	pub fn set_cookie(&self, url: &Url, set_cookie: &str) -> bool {
+		let cookie = match parse(url, set_cookie, SystemTime::now()) {
+			Some(cookie) => cookie,
+			None => return false,
+		};
+		let mut cookies = self.cookies.lock().unwrap();
+		cookies.retain(|old| {
+			           old.name != cookie.name || old.domain != cookie.domain || old.path != cookie.path
+			          });
+		if cookie.expires.map_or(false, |expires| expires <= SystemTime::now()) {
+			return false;
+		}
+		cookies.push(cookie);
+		true
+	}
+
+	/// Stores the cookies of all the `Set-Cookie` headers in `headers`.
+	pub fn store(&self, url: &Url, headers: &Headers) {
+		for value in headers.get_raw("Set-Cookie").unwrap_or(&[]) {
+			self.set_cookie(url, &String::from_utf8_lossy(value));
+		}
+	}
+
+	/// The `name=value` pairs to send with a request for `url`, the ones
+	/// with longer paths first.
+	pub fn cookies(&self, url: &Url) -> Vec<String> {
+		let now = SystemTime::now();
+		let host = match host(url) {
+			Some(host) => host,
+			None => return Vec::new(),
+		};
+		let mut cookies = self.cookies.lock().unwrap();
+		cookies.retain(|cookie| cookie.expires.map_or(true, |expires| expires > now));
+		let mut matching: Vec<&StoredCookie> = cookies.iter()
+		                                              .filter(|cookie| {
+			                                              cookie.matches(&host, url.path(), is_secure(url))
+			                                             })
+		                                              .collect();
+		matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+		matching.iter()
+		        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+		        .collect()
+	}
+
+	/// Throws away all cookies.
+	pub fn clear(&self) {
+		self.cookies.lock().unwrap().clear();
+	}
+}
+
+impl fmt::Debug for CookieJar {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		// the values are secrets more often than not
+		let cookies = self.cookies.lock().unwrap();
+		fmt.debug_list()
+		   .entries(cookies.iter().map(|cookie| format!("{}@{}{}", cookie.name, cookie.domain, cookie.path)))
+		   .finish()
+	}
+}
+
+impl StoredCookie {
+	fn matches(&self, host: &str, path: &str, secure: bool) -> bool {
+		let domain = if self.host_only {
+			host == self.domain
+		} else {
+			domain_match(host, &self.domain)
+		};
+		domain && path_match(path, &self.path) && (secure || !self.secure)
+	}
+}
+
+fn parse(url: &Url, set_cookie: &str, now: SystemTime) -> Option<StoredCookie> {
+	let host = match host(url) {
+		Some(host) => host,
+		None => return None,
+	};
+	let mut parts = set_cookie.split(';');
+	let pair = parts.next().unwrap_or("");
+	let eq = match pair.find('=') {
+		Some(eq) => eq,
+		None => return None,
+	};
+	let name = pair[..eq].trim();
+	if name.is_empty() {
+		return None;
+	}
+	let mut cookie = StoredCookie {
+		name: name.to_owned(),
+		value: pair[eq + 1..].trim().to_owned(),
+		domain: host.clone(),
+		host_only: true,
+		path: default_path(url.path()),
+		secure: false,
+		expires: None,
+	};
+
+	let mut max_age = None;
+	for attribute in parts {
+		let (key, value) = match attribute.find('=') {
+			Some(eq) => (attribute[..eq].trim(), attribute[eq + 1..].trim()),
+			None => (attribute.trim(), ""),
+		};
+		match &key.to_lowercase()[..] {
+			"domain" if !value.is_empty() => {
+				let domain = value.trim_start_matches('.').to_lowercase();
+				if !domain_match(&host, &domain) || (!domain.contains('.') && domain != host) {
+					return None;
+				}
+				cookie.host_only = false;
+				cookie.domain = domain;
+			}
+			"path" if value.starts_with('/') => cookie.path = value.to_owned(),
+			"secure" => cookie.secure = true,
+			"max-age" => {
+				max_age = value.parse::<i64>().ok().map(|seconds| if seconds <= 0 {
+					                                              UNIX_EPOCH
+					                                             } else {
+					                                              latest(now, Duration::from_secs(seconds as u64))
+					                                             })
+			}
+			"expires" if cookie.expires.is_none() => cookie.expires = parse_date(value),
+			_ => {}
+		}
+	}
+	if max_age.is_some() {
+		cookie.expires = max_age;
+	} else if let Some(expires) = cookie.expires {
+		cookie.expires = Some(expires.duration_since(now)
+		                             .map(|age| latest(now, age))
+		                             .unwrap_or(expires));
+	}
+	// only secure origins may set secure cookies
+	if cookie.secure && !is_secure(url) {
+		return None;
+	}
+	Some(cookie)
+}
+
+/// How long a cookie may be kept at most, after RFC 6265bis.
+const MAX_AGE: u64 = 400 * 24 * 60 * 60;
+
+/// `now + age`, the age capped to `MAX_AGE`.
+fn latest(now: SystemTime, age: Duration) -> SystemTime {
+	let age = ::std::cmp::min(age, Duration::from_secs(MAX_AGE));
+	now.checked_add(age).unwrap_or(now)
+}
+
+fn host(url: &Url) -> Option<String> {
+	match url.host() {
+		Some(Host::Domain(domain)) => Some(domain.to_lowercase()),
+		Some(Host::Ipv4(ip)) => Some(ip.to_string()),
+		Some(Host::Ipv6(ip)) => Some(ip.to_string()),
+		None => None,
+	}
+}
+
+fn is_secure(url: &Url) -> bool {
+	url.scheme() == "wss" || url.scheme() == "https"
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+	host == domain ||
+	(host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.') &&
+	 host.parse::<::std::net::IpAddr>().is_err())
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+	path == cookie_path ||
+	(path.starts_with(cookie_path) &&
+	 (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the path of the request.
+fn default_path(path: &str) -> String {
+	match path.rfind('/') {
+		Some(0) | None => "/".to_owned(),
+		Some(i) => path[..i].to_owned(),
+	}
+}
+
+/// Reads dates like `Wed, 21 Oct 2015 07:28:00 GMT`, or with dashes as
+/// in `Wed, 21-Oct-2015 07:28:00 GMT`.
+fn parse_date(date: &str) -> Option<SystemTime> {
+	const MONTHS: [&'static str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct",
+	                                    "nov", "dec"];
+	let fields: Vec<&str> = date.split(|c: char| c == ' ' || c == '-' || c == ',')
+	                            .filter(|field| !field.is_empty())
+	                            .collect();
+	if fields.len() < 5 {
+		return None;
+	}
+	let day: u64 = match fields[1].parse() {
+		Ok(day) if day >= 1 && day <= 31 => day,
+		_ => return None,
+	};
+	let month = match MONTHS.iter().position(|month| fields[2].to_lowercase().starts_with(month)) {
+		Some(month) => month as u64 + 1,
+		None => return None,
+	};
+	let year: u64 = match fields[3].parse() {
+		Ok(year) if year < 70 => year + 2000,
+		Ok(year) if year < 100 => year + 1900,
+		// a year has 2 to 4 digits
+		Ok(year) if year >= 1970 && year <= 9999 => year,
+		_ => return None,
+	};
+	let time: Vec<u64> = fields[4].split(':').filter_map(|field| field.parse().ok()).collect();
+	if time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+		return None;
+	}
+
+	// days since 1970-01-01 of the civil date
+	let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+	let era = y / 400;
+	let year_of_era = y - era * 400;
+	let day_of_year = (153 * m + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	let days = era * 146097 + day_of_era - 719468;
+
+	let seconds = days * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+	Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cookie_rules() {
+		let jar = CookieJar::new();
+		let login = Url::parse("https://www.example.com/account/login").unwrap();
+		assert!(jar.set_cookie(&login, "session=abc; Secure; Path=/; HttpOnly"));
+		assert!(jar.set_cookie(&login, "theme=dark; Domain=.example.com; Path=/"));
+		assert!(jar.set_cookie(&login, "draft=1"));
+		assert!(!jar.set_cookie(&login, "stolen=1; Domain=other.com"));
+		assert!(!jar.set_cookie(&login, "tld=1; Domain=com"));
+
+		let url = |url: &str| Url::parse(url).unwrap();
+		assert_eq!(jar.cookies(&url("wss://www.example.com/chat")), ["session=abc", "theme=dark"]);
+		assert_eq!(jar.cookies(&url("ws://www.example.com/chat")), ["theme=dark"]);
+		assert_eq!(jar.cookies(&url("ws://api.example.com/")), ["theme=dark"]);
+		assert_eq!(jar.cookies(&url("ws://www.example.com/account/ws")),
+		           ["draft=1", "theme=dark"]);
+		assert_eq!(jar.cookies(&url("ws://www.example.com/accounts")), ["theme=dark"]);
+
+		// replaced, then removed
+		assert!(jar.set_cookie(&login, "theme=light; Domain=example.com; Path=/"));
+		assert_eq!(jar.cookies(&url("ws://example.com/")), ["theme=light"]);
+		assert!(!jar.set_cookie(&login, "theme=; Domain=example.com; Path=/; Max-Age=0"));
+		assert!(!jar.set_cookie(&login, "draft=; Expires=Thu, 01 Jan 1970 00:00:00 GMT"));
+		assert_eq!(jar.cookies(&url("wss://www.example.com/account/ws")), ["session=abc"]);
+
+		// a secure cookie needs a secure origin
+		assert!(!jar.set_cookie(&url("ws://example.com/"), "a=1; Secure"));
+	}
+
+	#[test]
+	fn cookie_dates() {
+		let date = parse_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+		assert_eq!(date.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1445412480);
+		assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+		           Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+		assert_eq!(parse_date("tomorrow"), None);
+		assert_eq!(parse_date("Wed, 21 Oct 99999999999999 07:28:00 GMT"), None);
+	}
+
+	#[test]
+	fn caps_cookie_lifetimes() {
+		let jar = CookieJar::new();
+		let url = Url::parse("ws://example.com/").unwrap();
+		assert!(jar.set_cookie(&url, "a=1; Max-Age=9223372036854775807"));
+		assert!(jar.set_cookie(&url, "b=2; Expires=Fri, 31 Dec 9999 23:59:59 GMT"));
+		let cap = SystemTime::now() + Duration::from_secs(MAX_AGE);
+		for cookie in jar.cookies.lock().unwrap().iter() {
+			assert!(cookie.expires.unwrap() <= cap);
+		}
+		assert_eq!(jar.cookies(&url), ["a=1", "b=2"]);
+	}
+}
@@ -0,0 +1,589 @@
+//! Hand a live client over to another process on the same machine, so its
+//! connection survives a restart or a deploy.
+//! This module is only available on Unix.
+//!
+//! The socket is passed over a Unix domain socket as `SCM_RIGHTS` ancillary
+//! data, together with everything else the `Client` holds: the bytes read
+//! from the socket but not parsed yet, the frames of a fragmented message
+//! received so far, which side masks its frames, the headers of the
+//! handshake with the negotiated protocol and extensions, and for a server's
+//! client the upgrade request it was accepted with. Only clients over
+//! plain sockets can be handed over, the state of a TLS session can't be.
+//!
+//! The process going away hands its clients over:
+//!
+//! ```rust,no_run
+//! use std::net::TcpStream;
+//! use std::os::unix::net::UnixStream;
+//! use websocket::Client;
+//!
+//! # let clients: Vec<Client<TcpStream>> = Vec::new();
+//! let channel = UnixStream::connect("/run/chat/handoff.sock").unwrap();
+//! for client in clients {
+//!     client.hand_off(&channel).unwrap();
+//! }
+//! ```
+//!
+//! And the one taking over picks them up where they were:
+//!
+//! ```rust,no_run
+//! use std::net::TcpStream;
+//! use std::os::unix::net::UnixListener;
+//! use websocket::Client;
+//!
+//! let listener = UnixListener::bind("/run/chat/handoff.sock").unwrap();
+//! let (channel, _) = listener.accept().unwrap();
+//! while let Ok(client) = Client::<TcpStream>::take_over(&channel) {
+//!     // carry on as before
+//! }
+//! ```
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+use std::sync::Arc;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+
+use buffer::{BufReader, INIT_BUFFER_SIZE};
+use dataframe::{DataFrame, Opcode};
+use handshake::{HttpVersion, Incoming};
+use header::Headers;
+use receiver::Receiver;
+use result::{WebSocketResult, WebSocketError};
+use sender::Sender;
+use server::request::{UpgradeRequest, TrustedProxies};
+use stream::Stream;
+use super::Client;
+
+/// Marks the start of a serialized state, and its version.
+const MAGIC: &'static [u8; 4] = b"WSH2";
+
+/// The largest state `take_over` accepts.
+const MAX_STATE_SIZE: usize = 1 << 30;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// Everything a `Client` holds besides its stream.
+#[derive(Clone, Debug)]
+pub struct ClientState {
+	/// Bytes read from the stream that haven't been parsed yet.
+	pub buffer: Vec<u8>,
+	/// The headers of the handshake, which hold the negotiated protocol
+	/// and extensions.
+	pub headers: Headers,
+	/// Whether sent frames are masked, which clients do.
+	pub out_mask: bool,
+	/// Whether received frames have to be masked, which servers expect.
+	pub in_mask: bool,
+	/// The frames of a fragmented message received so far.
+	pub fragments: Vec<DataFrame>,
+	/// The upgrade request a server accepted the client with.
+	pub request: Option<UpgradeRequest>,
+}
+
+impl ClientState {
+	/// Serializes the state.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = MAGIC.to_vec();
+		bytes.push(self.out_mask as u8 | (self.in_mask as u8) << 1);
+		write_bytes(&mut bytes, &self.buffer);
+		write_headers(&mut bytes, &self.headers);
+
+		write_len(&mut bytes, self.fragments.len());
+		for frame in &self.fragments {
+			bytes.push(frame.finished as u8 | (frame.reserved[0] as u8) << 1 |
+			           (frame.reserved[1] as u8) << 2 | (frame.reserved[2] as u8) << 3);
+			bytes.push(frame.opcode as u8);
+			write_bytes(&mut bytes, &frame.data);
+		}
+
+		match self.request {
+			Some(ref request) => {
+				bytes.push(1);
+				write_request(&mut bytes, request);
+			}
+			None => bytes.push(0),
+		}
+		bytes
+	}
+
+	/// Parses a state serialized with `to_bytes`.
+	pub fn from_bytes(mut bytes: &[u8]) -> WebSocketResult<ClientState> {
+		let bytes = &mut bytes;
+		if !bytes.starts_with(MAGIC) {
+			return Err(WebSocketError::HandoffError("Not a client state"));
+		}
+		*bytes = &bytes[MAGIC.len()..];
+		let masks = try!(read_u8(bytes));
+		let buffer = try!(read_bytes(bytes));
+		let headers = try!(read_headers(bytes));
+
+		let count = try!(read_len(bytes));
+		let mut fragments = Vec::with_capacity(cmp::min(count, bytes.len()));
+		for _ in 0..count {
+			let flags = try!(read_u8(bytes));
+			let opcode = try!(Opcode::new(try!(read_u8(bytes)))
+			                      .ok_or(WebSocketError::HandoffError("Invalid opcode")));
+			fragments.push(DataFrame {
+			                   finished: flags & 1 != 0,
+			                   reserved: [flags & 2 != 0, flags & 4 != 0, flags & 8 != 0],
+			                   opcode: opcode,
+			                   data: try!(read_bytes(bytes)),
+			               });
+		}
+
+		let request = match try!(read_u8(bytes)) {
+			0 => None,
+			_ => Some(try!(read_request(bytes))),
+		};
+
+		if !bytes.is_empty() {
+			return Err(WebSocketError::HandoffError("Trailing bytes after the client state"));
+		}
+		Ok(ClientState {
+		       buffer: buffer,
+		       headers: headers,
+		       out_mask: masks & 1 != 0,
+		       in_mask: masks & 2 != 0,
+		       fragments: fragments,
+		       request: request,
+		   })
+	}
+}
+
+impl<S> Client<S>
+    where S: Stream
+{
+	/// Takes the client apart into its stream and the rest of its state,
+	/// which `Client::from_state` puts back together.
+	pub fn into_state(self) -> (S, ClientState) {
+		let (stream, buf, pos, cap) = self.stream.into_parts();
+		let state = ClientState {
+			buffer: buf[pos..cap].to_vec(),
+			headers: self.headers,
+			out_mask: self.sender.is_masked(),
+			in_mask: self.receiver.is_masked(),
+			fragments: self.receiver.fragments().to_vec(),
+			request: self.request
+			             .map(|request| Arc::try_unwrap(request).unwrap_or_else(|request| (*request).clone())),
+		};
+		(stream, state)
+	}
+
+	/// Rebuilds a client from its stream and state, it carries on exactly
+	/// where the client `into_state` was called on stopped.
+	pub fn from_state(stream: S, state: ClientState) -> Client<S> {
+		let ClientState {
+			mut buffer,
+			headers,
+			out_mask,
+			in_mask,
+			fragments,
+			request,
+		} = state;
+		let len = buffer.len();
+		buffer.resize(cmp::max(len, INIT_BUFFER_SIZE), 0);
+		Client {
+			stream: BufReader::from_parts(stream, buffer, 0, len),
+			headers: headers,
+			sender: Sender::new(out_mask),
+			receiver: Receiver::with_fragments(in_mask, fragments),
+			request: request.map(Arc::new),
+		}
+	}
+}
+
+impl<S> Client<S>
+    where S: Stream + AsRawFd
+{
+	/// Passes the client to the process on the other end of `channel`,
+	/// which gets it back with `Client::take_over`. The connection stays
+	/// open, it is only closed in this process.
+	pub fn hand_off(self, channel: &UnixStream) -> WebSocketResult<()> {
+		let (stream, state) = self.into_state();
+		let state = state.to_bytes();
+		let mut message = Vec::with_capacity(4 + state.len());
+		write_len(&mut message, state.len());
+		message.extend_from_slice(&state);
+
+		let sent = try!(send_with_fd(channel, &message, stream.as_raw_fd()));
+		try!((&*channel).write_all(&message[sent..]));
+		Ok(())
+	}
+}
+
+impl<S> Client<S>
+    where S: Stream + FromRawFd
+{
+	/// Receives a client passed with `Client::hand_off` from the process on
+	/// the other end of `channel`. This fails with `NoDataAvailable` once
+	/// that process has closed the channel.
+	pub fn take_over(channel: &UnixStream) -> WebSocketResult<Client<S>> {
+		let mut len = [0; 4];
+		let (read, fd) = try!(recv_with_fd(channel, &mut len));
+		if read == 0 {
+			return Err(WebSocketError::NoDataAvailable);
+		}
+		let stream = match fd {
+			Some(fd) => unsafe { S::from_raw_fd(fd) },
+			None => return Err(WebSocketError::HandoffError("No socket was passed with the client")),
+		};
+		try!((&*channel).read_exact(&mut len[read..]));
+
+		let len = try!((&len[..]).read_u32::<BigEndian>()) as usize;
+		if len > MAX_STATE_SIZE {
+			return Err(WebSocketError::HandoffError("Client state is too large"));
+		}
+		let mut state = vec![0; len];
+		try!((&*channel).read_exact(&mut state));
+		Ok(Client::from_state(stream, try!(ClientState::from_bytes(&state))))
+	}
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+	// writing to a Vec can't fail
+	let _ = bytes.write_u32::<BigEndian>(len as u32);
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+	write_len(bytes, data.len());
+	bytes.extend_from_slice(data);
+}
+
+fn write_headers(bytes: &mut Vec<u8>, headers: &Headers) {
+	write_len(bytes, headers.len());
+	for header in headers.iter() {
+		write_bytes(bytes, header.name().as_bytes());
+		write_len(bytes, header.raw().len());
+		for value in header.raw() {
+			write_bytes(bytes, value);
+		}
+	}
+}
+
+fn write_request(bytes: &mut Vec<u8>, request: &UpgradeRequest) {
+	let (ref method, ref target) = request.request.subject;
+	bytes.push(match request.request.version {
+		           HttpVersion::Http09 => 0,
+		           HttpVersion::Http10 => 1,
+		           HttpVersion::Http11 => 2,
+		           HttpVersion::Http20 => 3,
+		           HttpVersion::Http30 => 4,
+		       });
+	write_bytes(bytes, method.to_string().as_bytes());
+	write_bytes(bytes, target.to_string().as_bytes());
+	write_headers(bytes, &request.request.headers);
+	match request.trusted_proxies {
+		TrustedProxies::None => bytes.push(0),
+		TrustedProxies::All => bytes.push(1),
+		TrustedProxies::Only(ref addrs) => {
+			bytes.push(2);
+			write_len(bytes, addrs.len());
+			for addr in addrs {
+				write_bytes(bytes, addr.to_string().as_bytes());
+			}
+		}
+	}
+}
+
+fn truncated() -> WebSocketError {
+	WebSocketError::HandoffError("Truncated client state")
+}
+
+fn read_u8(bytes: &mut &[u8]) -> WebSocketResult<u8> {
+	bytes.read_u8().map_err(|_| truncated())
+}
+
+fn read_len(bytes: &mut &[u8]) -> WebSocketResult<usize> {
+	bytes.read_u32::<BigEndian>().map(|len| len as usize).map_err(|_| truncated())
+}
+
+fn read_string(bytes: &mut &[u8]) -> WebSocketResult<String> {
+	String::from_utf8(try!(read_bytes(bytes))).map_err(|_| WebSocketError::HandoffError("String is not UTF-8"))
+}
+
+fn read_headers(bytes: &mut &[u8]) -> WebSocketResult<Headers> {
+	let mut headers = Headers::new();
+	for _ in 0..try!(read_len(bytes)) {
+		let name = try!(read_string(bytes));
+		let count = try!(read_len(bytes));
+		let mut values = Vec::with_capacity(cmp::min(count, bytes.len()));
+		for _ in 0..count {
+			values.push(try!(read_bytes(bytes)));
+		}
+		headers.set_raw(name, values);
+	}
+	Ok(headers)
+}
+
+fn read_request(bytes: &mut &[u8]) -> WebSocketResult<UpgradeRequest> {
+	let version = match try!(read_u8(bytes)) {
+		0 => HttpVersion::Http09,
+		1 => HttpVersion::Http10,
+		2 => HttpVersion::Http11,
+		3 => HttpVersion::Http20,
+		4 => HttpVersion::Http30,
+		_ => return Err(WebSocketError::HandoffError("Invalid HTTP version")),
+	};
+	let method = try!(try!(read_string(bytes))
+		                  .parse()
+		                  .map_err(|_| WebSocketError::HandoffError("Invalid request method")));
+	let target = try!(try!(read_string(bytes))
+		                  .parse()
+		                  .map_err(|_| WebSocketError::HandoffError("Invalid request target")));
+	let headers = try!(read_headers(bytes));
+	let trusted_proxies = match try!(read_u8(bytes)) {
+		0 => TrustedProxies::None,
+		1 => TrustedProxies::All,
+		2 => {
+			let count = try!(read_len(bytes));
+			let mut addrs = Vec::with_capacity(cmp::min(count, bytes.len()));
+			for _ in 0..count {
+				addrs.push(try!(try!(read_string(bytes))
+					                .parse()
+					                .map_err(|_| WebSocketError::HandoffError("Invalid proxy address"))));
+			}
+			TrustedProxies::Only(addrs)
+		}
+		_ => return Err(WebSocketError::HandoffError("Invalid trusted proxies")),
+	};
+	Ok(UpgradeRequest {
+	       request: Incoming {
+	           version: version,
+	           subject: (method, target),
+	           headers: headers,
+	       },
+	       trusted_proxies: trusted_proxies,
+	   })
+}
+
+fn read_bytes(bytes: &mut &[u8]) -> WebSocketResult<Vec<u8>> {
+	let len = try!(read_len(bytes));
+	if bytes.len() < len {
+		return Err(truncated());
+	}
+	let (data, rest) = bytes.split_at(len);
+	*bytes = rest;
+	Ok(data.to_vec())
+}
+
+/// Room for the control message carrying one file descriptor, as `u64`s to
+/// keep it aligned for `cmsghdr`.
+fn control_buffer() -> Vec<u64> {
+	let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+	vec![0; (space + 7) / 8]
+}
+
+/// Sends some of `bytes` with `fd` attached, returning how many were sent.
+fn send_with_fd(channel: &UnixStream, bytes: &[u8], fd: RawFd) -> io::Result<usize> {
+	let mut control = control_buffer();
+	unsafe {
+		let mut iov = libc::iovec {
+			iov_base: bytes.as_ptr() as *mut libc::c_void,
+			iov_len: bytes.len(),
+		};
+		let mut msg: libc::msghdr = mem::zeroed();
+		msg.msg_iov = &mut iov;
+		msg.msg_iovlen = 1;
+		msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+		msg.msg_controllen = (control.len() * 8) as _;
+
+		let cmsg = libc::CMSG_FIRSTHDR(&msg);
+		(*cmsg).cmsg_level = libc::SOL_SOCKET;
+		(*cmsg).cmsg_type = libc::SCM_RIGHTS;
+		(*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+		ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+		loop {
+			let sent = libc::sendmsg(channel.as_raw_fd(), &msg, 0);
+			if sent >= 0 {
+				return Ok(sent as usize);
+			}
+			let error = io::Error::last_os_error();
+			if error.kind() != io::ErrorKind::Interrupted {
+				return Err(error);
+			}
+		}
+	}
+}
+
+/// Receives into `bytes`, returning how many bytes were read and the file
+/// descriptor attached to them. Any further descriptors are closed.
+fn recv_with_fd(channel: &UnixStream, bytes: &mut [u8]) -> io::Result<(usize, Option<RawFd>)> {
+	let mut control = control_buffer();
+	unsafe {
+		let mut iov = libc::iovec {
+			iov_base: bytes.as_mut_ptr() as *mut libc::c_void,
+			iov_len: bytes.len(),
+		};
+		let mut msg: libc::msghdr = mem::zeroed();
+		msg.msg_iov = &mut iov;
+		msg.msg_iovlen = 1;
+		msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+		msg.msg_controllen = (control.len() * 8) as _;
+
+		let read = loop {
+			let read = libc::recvmsg(channel.as_raw_fd(), &mut msg, RECV_FLAGS);
+			if read >= 0 {
+				break read as usize;
+			}
+			let error = io::Error::last_os_error();
+			if error.kind() != io::ErrorKind::Interrupted {
+				return Err(error);
+			}
+		};
+
+		let mut passed = None;
+		let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+		while !cmsg.is_null() {
+			if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+				let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+				let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+				for i in 0..len / mem::size_of::<RawFd>() {
+					let fd = ptr::read_unaligned(data.offset(i as isize));
+					if passed.is_none() {
+						passed = Some(fd);
+					} else {
+						libc::close(fd);
+					}
+				}
+			}
+			cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+		}
+
+		if let Some(fd) = passed {
+			// set atomically on Linux, the socket shouldn't leak into
+			// processes this one starts
+			if RECV_FLAGS == 0 && libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+				let error = io::Error::last_os_error();
+				libc::close(fd);
+				return Err(error);
+			}
+		}
+		Ok((read, passed))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::net::{TcpListener, TcpStream};
+	use std::process::{Command, Stdio};
+	use std::time::Duration;
+	use message::Message;
+	use handshake::Method;
+
+	/// Tells the test binary it was started to take over a client.
+	const CHANNEL_FD: &'static str = "WEBSOCKET_TEST_HANDOFF_FD";
+
+	fn upgrade_request() -> UpgradeRequest {
+		let mut headers = Headers::new();
+		headers.set_raw("Cookie", vec![b"session=abc".to_vec()]);
+		UpgradeRequest {
+			request: Incoming {
+				version: HttpVersion::Http11,
+				subject: (Method::Get, "/rooms/lobby?nick=ada".parse().unwrap()),
+				headers: headers,
+			},
+			trusted_proxies: TrustedProxies::Only(vec!["10.0.0.1".parse().unwrap()]),
+		}
+	}
+
+	#[test]
+	fn state_round_trip() {
+		let mut headers = Headers::new();
+		headers.set_raw("Sec-WebSocket-Protocol", vec![b"chat".to_vec()]);
+		headers.set_raw("Set-Cookie", vec![b"a=1".to_vec(), b"b=2".to_vec()]);
+		let mut fragment = DataFrame::new(false, Opcode::Binary, vec![1, 2, 3]);
+		fragment.reserved = [true, false, true];
+		let state = ClientState {
+			buffer: b"\x80\x02lo".to_vec(),
+			headers: headers,
+			out_mask: false,
+			in_mask: true,
+			fragments: vec![fragment, DataFrame::new(false, Opcode::Continuation, vec![4])],
+			request: Some(upgrade_request()),
+		};
+
+		let bytes = state.to_bytes();
+		let parsed = ClientState::from_bytes(&bytes).unwrap();
+		assert_eq!(parsed.buffer, state.buffer);
+		assert_eq!(parsed.fragments, state.fragments);
+		assert_eq!((parsed.out_mask, parsed.in_mask), (false, true));
+		assert_eq!(parsed.headers.get_raw("set-cookie"),
+		           Some(&[b"a=1".to_vec(), b"b=2".to_vec()][..]));
+		assert_eq!(parsed.headers.to_string(), state.headers.to_string());
+		let request = parsed.request.unwrap();
+		assert_eq!(request.request.subject, state.request.as_ref().unwrap().request.subject);
+		assert_eq!(request.cookie("session"), Some("abc"));
+		assert_eq!(request.trusted_proxies, TrustedProxies::Only(vec!["10.0.0.1".parse().unwrap()]));
+
+		assert!(ClientState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+		assert!(ClientState::from_bytes(b"nope").is_err());
+	}
+
+	#[test]
+	fn hand_off_to_another_process() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let stream = listener.accept().unwrap().0;
+
+		let mut headers = Headers::new();
+		headers.set_raw("Sec-WebSocket-Protocol", vec![b"chat".to_vec()]);
+		let mut server = Client::unchecked(BufReader::new(stream), headers, false, true)
+			.with_request(upgrade_request());
+		let mut peer = Client::unchecked(BufReader::new(peer), Headers::new(), true, false);
+
+		// leave the server halfway through a fragmented message
+		peer.send_dataframe(&DataFrame::new(false, Opcode::Text, b"hel".to_vec()))
+		    .unwrap();
+		server.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+		assert!(server.recv_message::<Message, _, _>().is_err());
+		server.set_read_timeout(None).unwrap();
+
+		let (channel, theirs) = UnixStream::pair().unwrap();
+		unsafe {
+			assert!(libc::fcntl(theirs.as_raw_fd(), libc::F_SETFD, 0) >= 0);
+		}
+		let mut child = Command::new(env::current_exe().unwrap())
+			.args(&["--exact", "client::handoff::tests::take_over_in_child"])
+			.env(CHANNEL_FD, theirs.as_raw_fd().to_string())
+			.stdout(Stdio::null())
+			.spawn()
+			.unwrap();
+		drop(theirs);
+
+		server.hand_off(&channel).unwrap();
+		peer.send_dataframe(&DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()))
+		    .unwrap();
+
+		let reply: Message = peer.recv_message().unwrap();
+		assert_eq!(&reply.payload[..], b"hello, chat, /rooms/lobby");
+		assert!(child.wait().unwrap().success());
+	}
+
+	/// The other half of `hand_off_to_another_process`, does nothing unless
+	/// started by it.
+	#[test]
+	fn take_over_in_child() {
+		let fd = match env::var(CHANNEL_FD) {
+			Ok(fd) => fd.parse().unwrap(),
+			Err(_) => return,
+		};
+		let channel = unsafe { UnixStream::from_raw_fd(fd) };
+
+		let mut client = Client::<TcpStream>::take_over(&channel).unwrap();
+		let protocol = client.protocols()[0].clone();
+		let message: Message = client.recv_message().unwrap();
+		let path = client.request().unwrap().path().to_owned();
+		let reply = format!("{}, {}, {}", String::from_utf8_lossy(&message.payload), protocol, path);
+		client.send_message(&Message::text(reply)).unwrap();
+	}
+}
@@ -1,19 +1,26 @@
 //! Contains the WebSocket client.
 extern crate url;
 
-use std::net::TcpStream;
-use std::net::SocketAddr;
+use std::any::Any;
+use std::fs::File;
+use std::ops::Range;
+use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::io::Result as IoResult;
 use std::io::{Read, Write};
-use hyper::header::Headers;
-use hyper::buffer::BufReader;
+use header::Headers;
+use buffer::BufReader;
+use server::request::UpgradeRequest;
 
 use ws;
 use ws::sender::Sender as SenderTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
 use ws::receiver::Receiver as ReceiverTrait;
 use result::WebSocketResult;
-use stream::{AsTcpStream, Stream, Splittable, Shutdown};
+use stream::{Transport, Stream, Splittable, Shutdown, SharedStream, TlsSession};
+#[cfg(unix)]
+use stream::{UnixStream, UCred, peer_cred};
 use dataframe::DataFrame;
 use header::{WebSocketProtocol, WebSocketExtensions};
 use header::extensions::Extension;
@@ -21,12 +28,39 @@ use header::extensions::Extension;
 use ws::dataframe::DataFrame as DataFrameable;
 use sender::Sender;
 use receiver::Receiver;
-pub use sender::Writer;
+pub use sender::{Writer, SharedWriter};
 pub use receiver::Reader;
 
 pub mod builder;
 pub use self::builder::{ClientBuilder, Url, ParseError};
 
+mod auth;
+pub use self::auth::{Credentials, Challenge, ProvideCredentials};
+mod cookies;
+pub use self::cookies::CookieJar;
+
+#[cfg(unix)]
+pub mod handoff;
+mod proxy;
+mod reconnect;
+pub use self::reconnect::{ReconnectingClient, Backoff, Buffering, ConnectionEvent};
+mod redirect;
+pub use self::redirect::RedirectPolicy;
+mod resolve;
+pub use self::resolve::{Resolve, SystemResolver};
+mod sendfile;
+mod tcp;
+#[cfg(any(feature="ssl", feature="rustls"))]
+mod tls;
+
+/// The most of the body of a response refusing the upgrade that is kept in
+/// a `WebSocketError::UpgradeRefused`.
+pub const MAX_REFUSAL_BODY_SIZE: usize = 64 * 1024;
+
+/// The largest data frame `Client::send_file` will send, files bigger than
+/// this are sent as a fragmented message.
+pub const DEFAULT_FILE_FRAGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
 /// Represents a WebSocket client, which can send and receive messages/data frames.
 ///
 /// The client just wraps around a `Stream` (which is something that can be read from
@@ -37,7 +71,7 @@ pub use self::builder::{ClientBuilder, Url, ParseError};
 /// to different threads, often using a send loop and receiver loop concurrently,
 /// as shown in the client example in `examples/client.rs`.
 /// This is only possible for streams that implement the `Splittable` trait, which
-/// currently is only TCP streams. (it is unsafe to duplicate an SSL stream)
+/// currently is only TCP and Unix streams. (it is unsafe to duplicate an SSL stream)
 ///
 ///# Connecting to a Server
 ///
@@ -63,52 +97,141 @@ pub struct Client<S>
 	headers: Headers,
 	sender: Sender,
 	receiver: Receiver,
+	request: Option<Arc<UpgradeRequest>>,
 }
 
-impl Client<TcpStream> {
+impl<S> Client<S>
+    where S: Transport + Stream
+{
+	/// Shuts down the client connection, will cause all pending and future IO to
+	/// return immediately with an appropriate value.
+	pub fn shutdown(&self) -> IoResult<()> {
+		self.stream.get_ref().shutdown(Shutdown::Both)
+	}
+
 	/// Shuts down the sending half of the client connection, will cause all pending
 	/// and future IO to return immediately with an appropriate value.
 	pub fn shutdown_sender(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Write)
+		self.stream.get_ref().shutdown(Shutdown::Write)
 	}
 
 	/// Shuts down the receiving half of the client connection, will cause all pending
 	/// and future IO to return immediately with an appropriate value.
 	pub fn shutdown_receiver(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Read)
-	}
-}
-
-impl<S> Client<S>
-    where S: AsTcpStream + Stream
-{
-	/// Shuts down the client connection, will cause all pending and future IO to
-	/// return immediately with an appropriate value.
-	pub fn shutdown(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
+		self.stream.get_ref().shutdown(Shutdown::Read)
 	}
 
-	/// See [`TcpStream::peer_addr`]
+	/// The address of the remote end of the connection,
+	/// see [`TcpStream::peer_addr`]
 	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.peer_addr).
-	pub fn peer_addr(&self) -> IoResult<SocketAddr> {
-		self.stream.get_ref().as_tcp().peer_addr()
+	pub fn peer_addr(&self) -> IoResult<S::Addr> {
+		self.stream.get_ref().peer_addr()
 	}
 
-	/// See [`TcpStream::local_addr`]
+	/// The address of the local end of the connection,
+	/// see [`TcpStream::local_addr`]
 	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.local_addr).
-	pub fn local_addr(&self) -> IoResult<SocketAddr> {
-		self.stream.get_ref().as_tcp().local_addr()
+	pub fn local_addr(&self) -> IoResult<S::Addr> {
+		self.stream.get_ref().local_addr()
 	}
 
 	/// See [`TcpStream::set_nodelay`]
 	/// (https://doc.rust-lang.org/std/net/struct.TcpStream.html#method.set_nodelay).
 	pub fn set_nodelay(&mut self, nodelay: bool) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().set_nodelay(nodelay)
+		self.stream.get_ref().set_nodelay(nodelay)
+	}
+
+	/// Sets the timeout for reading from the connection, `None` blocks indefinitely.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().set_read_timeout(timeout)
+	}
+
+	/// Sets the timeout for writing to the connection, `None` blocks indefinitely.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().set_write_timeout(timeout)
 	}
 
 	/// Changes whether the stream is in nonblocking mode.
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
+		self.stream.get_ref().set_nonblocking(nonblocking)
+	}
+
+	/// The certificate chain the peer presented and the cipher suite and
+	/// protocol version that were negotiated, `None` if the connection
+	/// doesn't use TLS.
+	pub fn tls_session(&self) -> Option<TlsSession> {
+		self.stream.get_ref().tls_session()
+	}
+}
+
+impl<S> Client<S>
+    where S: Transport<Addr = SocketAddr> + Stream
+{
+	/// The address of the client a server accepted, that of the peer unless
+	/// the upgrade request came through a trusted proxy, see
+	/// `UpgradeRequest::client_addr`.
+	pub fn client_addr(&self) -> IoResult<IpAddr> {
+		let peer = try!(self.peer_addr()).ip();
+		Ok(self.request().map_or(peer, |request| request.client_addr(peer)))
+	}
+}
+
+impl<S> Client<S>
+    where S: Transport + Stream
+{
+	/// Split this client into a Receiver and a Sender that share the stream,
+	/// for streams that can't be split otherwise such as SSL streams.
+	///
+	/// Both halves can be sent to different threads, and the Sender can be
+	/// cloned to send from several threads at once. The stream is put in
+	/// nonblocking mode so that waiting for a message doesn't stop others
+	/// from being sent.
+	///
+	///```no_run
+	///# extern crate websocket;
+	///# fn main() {
+	///use std::thread;
+	///use websocket::{ClientBuilder, Message};
+	///
+	///// the same goes for a TLS client from `connect_secure`
+	///let client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	///                     .connect_insecure().unwrap();
+	///
+	///let (mut receiver, sender) = client.split_shared().unwrap();
+	///
+	///let mut pinger = sender.clone();
+	///thread::spawn(move || {
+	///    pinger.send_message(&Message::ping(b"are you there?".to_vec())).unwrap();
+	///});
+	///
+	///for message in receiver.incoming_messages() {
+	///    let message: Message = message.unwrap();
+	///    println!("Recv: {:?}", message);
+	///}
+	///# }
+	///```
+	pub fn split_shared(self) -> IoResult<(Reader<SharedStream<S>>, SharedWriter<S>)> {
+		let (stream, buf, pos, cap) = self.stream.into_parts();
+		let stream = try!(SharedStream::new(stream));
+		Ok((Reader {
+		        stream: BufReader::from_parts(stream.clone(), buf, pos, cap),
+		        receiver: self.receiver,
+		        request: self.request.clone(),
+		    },
+		    SharedWriter {
+		        stream: stream,
+		        sender: self.sender,
+		        request: self.request,
+		    }))
+	}
+}
+
+#[cfg(unix)]
+impl Client<UnixStream> {
+	/// The user, group and (where available) process ID of the process on
+	/// the other end of the socket.
+	pub fn peer_cred(&self) -> IoResult<UCred> {
+		peer_cred(self.stream.get_ref())
 	}
 }
 
@@ -133,9 +256,17 @@ impl<S> Client<S>
 			// https://tools.ietf.org/html/rfc6455#section-5
 			sender: Sender::new(out_mask),
 			receiver: Receiver::new(in_mask),
+			request: None,
 		}
 	}
 
+	/// Keeps the upgrade request a server accepted this client with.
+	#[doc(hidden)]
+	pub fn with_request(mut self, request: UpgradeRequest) -> Self {
+		self.request = Some(Arc::new(request));
+		self
+	}
+
 	/// Sends a single data frame to the remote endpoint.
 	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
 		where D: DataFrameable
@@ -151,6 +282,52 @@ impl<S> Client<S>
 		self.sender.send_message(self.stream.get_mut(), message)
 	}
 
+	/// Sends a range of bytes from a file as a single binary message.
+	/// Files larger than `DEFAULT_FILE_FRAGMENT_SIZE` are sent as a fragmented
+	/// message.
+	///
+	/// When this is a server's client over a plain `TcpStream` the file's contents
+	/// go straight from the file to the socket using `sendfile(2)` (on Linux),
+	/// without ever being copied into userspace. Otherwise (SSL, masked client
+	/// frames, other streams) the file is copied through a buffer.
+	///
+	/// The file is sent in one go, in nonblocking mode a stream that would
+	/// block partway through is an error as the frame is left half written.
+	///
+	/// ```rust,no_run
+	/// use std::fs::File;
+	/// use websocket::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let mut client = server.accept().ok().unwrap().accept().unwrap();
+	///
+	/// let file = File::open("blob.bin").unwrap();
+	/// let len = file.metadata().unwrap().len();
+	/// client.send_file(&file, 0..len).unwrap();
+	/// ```
+	pub fn send_file(&mut self, file: &File, range: Range<u64>) -> WebSocketResult<()>
+		where S: Any
+	{
+		self.send_file_fragmented(file, range, DEFAULT_FILE_FRAGMENT_SIZE)
+	}
+
+	/// Same as `send_file` but the message is split into data frames
+	/// carrying at most `fragment_size` bytes each.
+	pub fn send_file_fragmented(
+		&mut self,
+		file: &File,
+		range: Range<u64>,
+		fragment_size: u64,
+	) -> WebSocketResult<()>
+		where S: Any
+	{
+		sendfile::send_file(self.stream.get_mut(),
+		                    self.sender.is_masked(),
+		                    file,
+		                    range,
+		                    fragment_size)
+	}
+
 	/// Reads a single data frame from the remote endpoint.
 	pub fn recv_dataframe(&mut self) -> WebSocketResult<DataFrame> {
 		self.receiver.recv_dataframe(&mut self.stream)
@@ -210,6 +387,43 @@ impl<S> Client<S>
 		    .unwrap_or(&[])
 	}
 
+	/// The subprotocol the server chose, for a client that connected to a
+	/// server. The handshake made sure it's one of the offered ones. Always
+	/// `None` for a client the server side accepted, see `protocols` for what
+	/// that client asked for.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let client = ClientBuilder::new("ws://example.com/chat").unwrap()
+	///     .add_protocols(vec!["chat.v2", "chat.v1"])
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let v2 = client.protocol() == Some("chat.v2");
+	/// ```
+	pub fn protocol(&self) -> Option<&str> {
+		if !self.sender.is_masked() {
+			return None;
+		}
+		self.protocols().first().map(|protocol| &protocol[..])
+	}
+
+	/// The upgrade request a server accepted this client with, `None` for a
+	/// client that connected to a server.
+	///
+	/// ```rust,no_run
+	/// use websocket::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let client = server.accept().ok().unwrap().accept().unwrap();
+	///
+	/// let request = client.request().unwrap();
+	/// println!("{} wants {:?}", request.path(), request.query_param("room"));
+	/// ```
+	pub fn request(&self) -> Option<&UpgradeRequest> {
+		self.request.as_ref().map(|request| &**request)
+	}
+
 	/// If you supplied a protocol, be sure to check if it was accepted by the
 	/// server here. Since no extensions are implemented out of the box yet, using
 	/// one will require its own implementation.
@@ -387,10 +601,81 @@ impl<S> Client<S>
 		Ok((Reader {
 		        stream: BufReader::from_parts(read, buf, pos, cap),
 		        receiver: self.receiver,
+		        request: self.request.clone(),
 		    },
 		    Writer {
 		        stream: write,
 		        sender: self.sender,
+		        request: self.request,
 		    }))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+	use message::Message;
+	use server::upgrade::IntoWs;
+
+	/// Reads up to the end of an HTTP head.
+	pub fn read_head<R: Read>(stream: &mut R) -> Vec<u8> {
+		let mut head = Vec::new();
+		let mut byte = [0];
+		while !head.ends_with(b"\r\n\r\n") {
+			stream.read_exact(&mut byte).unwrap();
+			head.push(byte[0]);
+		}
+		head
+	}
+
+	#[test]
+	fn shared_split_sends_whole_messages() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", listener.local_addr().unwrap());
+
+		// echo everything back
+		let server = thread::spawn(move || {
+			let stream = listener.accept().unwrap().0;
+			let mut client = stream.into_ws().ok().unwrap().accept().ok().unwrap();
+			for _ in 0..100 {
+				let message: Message = client.recv_message().unwrap();
+				client.send_message(&message).unwrap();
+			}
+		});
+
+		let client: Client<TcpStream> = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		let (mut receiver, sender) = client.split_shared().unwrap();
+
+		// the reader is already waiting when the writers start
+		let reader = thread::spawn(move || {
+			let mut received = Vec::new();
+			for _ in 0..100 {
+				let message: Message = receiver.recv_message().unwrap();
+				received.push(message.payload.into_owned());
+			}
+			received
+		});
+
+		let writers: Vec<_> = (0..4u8)
+			.map(|n| {
+				let mut sender = sender.clone();
+				thread::spawn(move || for _ in 0..25 {
+				                  sender.send_message(&Message::binary(vec![n; 70000])).unwrap();
+				              })
+			})
+			.collect();
+		for writer in writers {
+			writer.join().unwrap();
+		}
+
+		let received = reader.join().unwrap();
+		assert_eq!(received.len(), 100);
+		for payload in received {
+			assert_eq!(payload.len(), 70000);
+			assert!(payload.iter().all(|b| *b == payload[0]));
+		}
+		server.join().unwrap();
+	}
+}
@@ -0,0 +1,545 @@
+//! A client that connects again when its connection is lost.
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use rand;
+use url::Url;
+
+use message::{Message, Type};
+use result::{WebSocketResult, WebSocketError};
+#[cfg(feature="ssl")]
+use stream::NetworkStream;
+use stream::Stream;
+use super::{Client, ClientBuilder};
+
+/// How long to wait before connecting again, growing exponentially with
+/// every round of failed attempts and every connection that didn't last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+	initial: Duration,
+	max: Duration,
+	factor: f64,
+	jitter: f64,
+	max_rounds: Option<u32>,
+	reset_after: Duration,
+}
+
+impl Backoff {
+	/// Waits `initial` after the first failed round, twice as long after
+	/// the next and so on, but never longer than `max`.
+	pub fn exponential(initial: Duration, max: Duration) -> Self {
+		Backoff {
+			initial: initial,
+			max: max,
+			factor: 2.0,
+			jitter: 0.5,
+			max_rounds: None,
+			reset_after: max,
+		}
+	}
+
+	/// What the wait is multiplied by after every failed round, 2 by
+	/// default.
+	pub fn factor(mut self, factor: f64) -> Self {
+		self.factor = factor.max(1.0);
+		self
+	}
+
+	/// Takes a random part, up to `jitter` (between 0 and 1, 0.5 by default),
+	/// off every wait, so clients that lost their connection at once don't
+	/// all come back at once.
+	pub fn jitter(mut self, jitter: f64) -> Self {
+		self.jitter = jitter.max(0.0).min(1.0);
+		self
+	}
+
+	/// Gives up after `rounds` rounds of failed attempts, a round being one
+	/// attempt for every URL. Without a limit, which is the default, the
+	/// client tries forever.
+	pub fn max_rounds(mut self, rounds: u32) -> Self {
+		self.max_rounds = Some(rounds);
+		self
+	}
+
+	/// How long a connection has to stay up for the waits to start over
+	/// from the initial one once it is lost, `max` by default. A connection
+	/// lost sooner makes the next wait longer, like a failed round.
+	pub fn reset_after(mut self, up: Duration) -> Self {
+		self.reset_after = up;
+		self
+	}
+
+	/// The wait after the `round`th failed round, counting from 0, before
+	/// jitter.
+	pub fn delay(&self, round: u32) -> Duration {
+		let initial = duration_secs(self.initial);
+		let delay = initial * self.factor.powi(round.min(i32::max_value() as u32) as i32);
+		secs_duration(delay.min(duration_secs(self.max)))
+	}
+
+	fn jittered(&self, round: u32) -> Duration {
+		let delay = duration_secs(self.delay(round));
+		secs_duration(delay * (1.0 - self.jitter * rand::random::<f64>()))
+	}
+}
+
+impl Default for Backoff {
+	/// Half a second, doubling up to 30 seconds.
+	fn default() -> Self {
+		Backoff::exponential(Duration::from_millis(500), Duration::from_secs(30))
+	}
+}
+
+fn duration_secs(duration: Duration) -> f64 {
+	duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn secs_duration(secs: f64) -> Duration {
+	Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+}
+
+/// What happens to messages sent while there is no connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Buffering {
+	/// They are thrown away.
+	Drop,
+	/// Up to this many are kept, and sent once connected again. When more
+	/// come the oldest are thrown away.
+	Buffer(usize),
+}
+
+/// A change of the connection of a `ReconnectingClient`.
+#[derive(Debug)]
+pub enum ConnectionEvent {
+	/// Connecting to this URL.
+	Connecting(Url),
+	/// Connected to this URL, and the on connect hook ran.
+	Connected(Url),
+	/// Connecting to this URL failed.
+	ConnectFailed(Url, WebSocketError),
+	/// The connection was lost, because of this error or, if there is none,
+	/// because the server closed it.
+	Disconnected(Option<WebSocketError>),
+	/// Every URL failed, waiting this long before trying again.
+	Waiting(Duration),
+	/// The backoff allows no more tries.
+	GaveUp,
+}
+
+/// A client that connects again, with backoff, whenever its connection is
+/// lost, trying fallback URLs in turn.
+///
+/// Receiving waits for a connection if there is none, sending while there
+/// is none keeps or drops the message as the `Buffering` says. After every
+/// connection the on connect hook runs, before messages kept meanwhile are
+/// sent.
+///
+/// ```rust,no_run
+/// # use websocket::ClientBuilder;
+/// use websocket::Message;
+/// use websocket::client::{ReconnectingClient, ConnectionEvent};
+///
+/// let mut client = ReconnectingClient::insecure(ClientBuilder::new("ws://primary.example.com").unwrap())
+///     .fallback(ClientBuilder::new("ws://backup.example.com").unwrap())
+///     .on_connect(|client| client.send_message(&Message::text("subscribe prices")))
+///     .on_event(|event: &ConnectionEvent| println!("{:?}", event));
+///
+/// loop {
+///     let message: Message = client.recv_message().unwrap();
+///     // ...
+/// }
+/// ```
+pub struct ReconnectingClient<S>
+	where S: Stream
+{
+	endpoints: Vec<ClientBuilder<'static>>,
+	current: usize,
+	connect: Box<FnMut(&mut ClientBuilder<'static>) -> WebSocketResult<Client<S>> + Send>,
+	backoff: Backoff,
+	buffering: Buffering,
+	pending: VecDeque<Message<'static>>,
+	on_connect: Option<Box<FnMut(&mut Client<S>) -> WebSocketResult<()> + Send>>,
+	on_event: Option<Box<FnMut(&ConnectionEvent) + Send>>,
+	client: Option<Client<S>>,
+	closed: bool,
+	// the backoff round, kept across connections that don't last
+	round: u32,
+	connected_at: Option<Instant>,
+	dropped: bool,
+}
+
+impl ReconnectingClient<TcpStream> {
+	/// Connects with `ClientBuilder::connect_insecure`.
+	pub fn insecure(builder: ClientBuilder<'static>) -> Self {
+		ReconnectingClient::with_connector(builder, |builder| builder.connect_insecure())
+	}
+}
+
+#[cfg(feature="ssl")]
+impl ReconnectingClient<Box<NetworkStream>> {
+	/// Connects with `ClientBuilder::connect`, over TLS for `wss://` URLs.
+	pub fn new(builder: ClientBuilder<'static>) -> Self {
+		ReconnectingClient::with_connector(builder, |builder| builder.connect(None))
+	}
+}
+
+impl<S> ReconnectingClient<S>
+	where S: Stream
+{
+	/// Connects with `connect`, like `|builder| builder.connect_unix()`.
+	pub fn with_connector<F>(builder: ClientBuilder<'static>, connect: F) -> Self
+		where F: FnMut(&mut ClientBuilder<'static>) -> WebSocketResult<Client<S>> + Send + 'static
+	{
+		ReconnectingClient {
+			endpoints: vec![builder],
+			current: 0,
+			connect: Box::new(connect),
+			backoff: Backoff::default(),
+			buffering: Buffering::Buffer(1024),
+			pending: VecDeque::new(),
+			on_connect: None,
+			on_event: None,
+			client: None,
+			closed: false,
+			round: 0,
+			connected_at: None,
+			dropped: false,
+		}
+	}
+
+	/// Adds a builder to try when the ones before fail.
+	pub fn fallback(mut self, builder: ClientBuilder<'static>) -> Self {
+		self.endpoints.push(builder);
+		self
+	}
+
+	/// How long to wait between rounds of failed attempts.
+	pub fn backoff(mut self, backoff: Backoff) -> Self {
+		self.backoff = backoff;
+		self
+	}
+
+	/// What to do with messages sent while disconnected, by default the
+	/// last 1024 are kept.
+	pub fn buffering(mut self, buffering: Buffering) -> Self {
+		self.buffering = buffering;
+		self
+	}
+
+	/// Runs `hook` on every new connection, say to log in or subscribe
+	/// again. If it fails the connection counts as failed.
+	pub fn on_connect<F>(mut self, hook: F) -> Self
+		where F: FnMut(&mut Client<S>) -> WebSocketResult<()> + Send + 'static
+	{
+		self.on_connect = Some(Box::new(hook));
+		self
+	}
+
+	/// Calls `listener` with every change of the connection.
+	pub fn on_event<F>(mut self, listener: F) -> Self
+		where F: FnMut(&ConnectionEvent) + Send + 'static
+	{
+		self.on_event = Some(Box::new(listener));
+		self
+	}
+
+	/// Connects unless connected already, trying the URLs in turn and
+	/// waiting between rounds until one works or the backoff gives up.
+	/// After a lost connection there is a wait before the first try too.
+	pub fn connect(&mut self) -> WebSocketResult<()> {
+		if self.closed {
+			return Err(closed());
+		}
+		if self.client.is_none() && self.dropped {
+			self.dropped = false;
+			self.wait();
+		}
+		let mut failed = 0;
+		let mut tried = 0;
+		while self.client.is_none() {
+			let url = self.endpoints[self.current].url().clone();
+			self.emit(ConnectionEvent::Connecting(url.clone()));
+			match self.establish() {
+				Ok(client) => {
+					self.client = Some(client);
+					self.connected_at = Some(Instant::now());
+					self.emit(ConnectionEvent::Connected(url));
+					break;
+				}
+				Err(e) => {
+					let event = ConnectionEvent::ConnectFailed(url, e);
+					self.emit(event);
+				}
+			}
+
+			// the next one is tried right away, until every one failed
+			self.current = (self.current + 1) % self.endpoints.len();
+			tried += 1;
+			if tried < self.endpoints.len() {
+				continue;
+			}
+			tried = 0;
+			failed += 1;
+			if self.backoff.max_rounds.map_or(false, |max| failed >= max) {
+				self.emit(ConnectionEvent::GaveUp);
+				return Err(WebSocketError::IoError(io::Error::new(io::ErrorKind::NotConnected,
+				                                                  "gave up connecting")));
+			}
+			self.wait();
+		}
+		Ok(())
+	}
+
+	/// Waits as long as the backoff says for the current round, and moves
+	/// on to the next.
+	fn wait(&mut self) {
+		let delay = self.backoff.jittered(self.round);
+		self.emit(ConnectionEvent::Waiting(delay));
+		thread::sleep(delay);
+		self.round = self.round.saturating_add(1);
+	}
+
+	/// Connects, runs the hook and sends what was kept.
+	fn establish(&mut self) -> WebSocketResult<Client<S>> {
+		let mut client = try!((self.connect)(&mut self.endpoints[self.current]));
+		if let Some(ref mut hook) = self.on_connect {
+			try!(hook(&mut client));
+		}
+		while let Some(message) = self.pending.pop_front() {
+			if let Err(e) = client.send_message(&message) {
+				self.pending.push_front(message);
+				return Err(e);
+			}
+		}
+		Ok(client)
+	}
+
+	/// Whether there is a connection right now.
+	pub fn is_connected(&self) -> bool {
+		self.client.is_some()
+	}
+
+	/// The client of the current connection, if there is one.
+	pub fn client(&mut self) -> Option<&mut Client<S>> {
+		self.client.as_mut()
+	}
+
+	/// The messages kept to be sent once connected.
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Sends `message` if connected, or else keeps or drops it. Failing to
+	/// send it loses the connection, and it's then kept or dropped as well,
+	/// which isn't an error.
+	pub fn send_message(&mut self, message: &Message) -> WebSocketResult<()> {
+		if self.closed {
+			return Err(closed());
+		}
+		let result = match self.client {
+			Some(ref mut client) => client.send_message(message),
+			None => {
+				self.hold(message);
+				return Ok(());
+			}
+		};
+		if let Err(e) = result {
+			self.disconnect(Some(e));
+			self.hold(message);
+		}
+		Ok(())
+	}
+
+	/// Receives the next message, connecting again for as long as the
+	/// backoff allows whenever the connection is lost. A close of the
+	/// server is answered, then the client connects again.
+	pub fn recv_message(&mut self) -> WebSocketResult<Message<'static>> {
+		loop {
+			try!(self.connect());
+			let result = match self.client {
+				Some(ref mut client) => client.recv_message(),
+				None => continue,
+			};
+			match result {
+				Ok(message) => {
+					let message: Message = message;
+					if message.opcode != Type::Close {
+						return Ok(message);
+					}
+					if let Some(ref mut client) = self.client {
+						let _ = client.send_message(&Message::close());
+					}
+					self.disconnect(None);
+				}
+				Err(e) => self.disconnect(Some(e)),
+			}
+		}
+	}
+
+	/// Closes the connection for good, no more connections are made.
+	pub fn close(&mut self) -> WebSocketResult<()> {
+		self.closed = true;
+		self.pending.clear();
+		match self.client.take() {
+			Some(mut client) => client.send_message(&Message::close()),
+			None => Ok(()),
+		}
+	}
+
+	fn disconnect(&mut self, error: Option<WebSocketError>) {
+		// dropping the client closes the connection
+		self.client = None;
+		self.dropped = true;
+		let reset_after = self.backoff.reset_after;
+		if self.connected_at.take().map_or(false, |at| at.elapsed() >= reset_after) {
+			self.round = 0;
+		}
+		self.emit(ConnectionEvent::Disconnected(error));
+	}
+
+	fn hold(&mut self, message: &Message) {
+		let limit = match self.buffering {
+			Buffering::Drop => return,
+			Buffering::Buffer(limit) => limit,
+		};
+		if limit == 0 {
+			return;
+		}
+		while self.pending.len() >= limit {
+			self.pending.pop_front();
+		}
+		self.pending.push_back(Message {
+			                       opcode: message.opcode,
+			                       cd_status_code: message.cd_status_code,
+			                       payload: Cow::Owned(message.payload.to_vec()),
+			                   });
+	}
+
+	fn emit(&mut self, event: ConnectionEvent) {
+		if let Some(ref mut listener) = self.on_event {
+			listener(&event);
+		}
+	}
+}
+
+fn closed() -> WebSocketError {
+	WebSocketError::IoError(io::Error::new(io::ErrorKind::NotConnected, "client was closed"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use std::sync::{Arc, Mutex};
+	use server::Server;
+
+	#[test]
+	fn backoff_delays() {
+		let backoff = Backoff::exponential(Duration::from_millis(100), Duration::from_secs(1));
+		assert_eq!(backoff.delay(0), Duration::from_millis(100));
+		assert_eq!(backoff.delay(3), Duration::from_millis(800));
+		assert_eq!(backoff.delay(40), Duration::from_secs(1));
+
+		let jittered = backoff.jitter(0.25).jittered(2);
+		assert!(jittered <= Duration::from_millis(400) && jittered >= Duration::from_millis(300));
+	}
+
+	#[test]
+	fn reconnects_and_fails_over() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		// takes connections and drops them, keeping the port from being
+		// reused by another test
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let dead = format!("ws://{}", listener.local_addr().unwrap());
+		thread::spawn(move || for stream in listener.incoming() {
+		                  drop(stream);
+		              });
+
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let log = events.clone();
+		let mut client = ReconnectingClient::insecure(ClientBuilder::new(&dead).unwrap())
+			.fallback(ClientBuilder::new(&url).unwrap())
+			.backoff(Backoff::exponential(Duration::from_millis(1), Duration::from_millis(10)))
+			.on_connect(|client| client.send_message(&Message::text("hello")))
+			.on_event(move |event: &ConnectionEvent| {
+				let name = match *event {
+					ConnectionEvent::Connecting(_) => "connecting",
+					ConnectionEvent::Connected(_) => "connected",
+					ConnectionEvent::ConnectFailed(..) => "failed",
+					ConnectionEvent::Disconnected(_) => "disconnected",
+					ConnectionEvent::Waiting(_) => "waiting",
+					ConnectionEvent::GaveUp => "gave up",
+				};
+				log.lock().unwrap().push(name);
+			});
+
+		// sent once connected, after the hook
+		client.send_message(&Message::text("early")).unwrap();
+		assert_eq!(client.pending(), 1);
+
+		let remote = thread::spawn(move || {
+			let mut first = server.accept().ok().unwrap().accept().unwrap();
+			let hello: Message = first.recv_message().unwrap();
+			assert_eq!(hello, Message::text("hello"));
+			let early: Message = first.recv_message().unwrap();
+			assert_eq!(early, Message::text("early"));
+			first.send_message(&Message::close()).unwrap();
+
+			let mut second = server.accept().ok().unwrap().accept().unwrap();
+			let hello: Message = second.recv_message().unwrap();
+			assert_eq!(hello, Message::text("hello"));
+			second.send_message(&Message::text("welcome back")).unwrap();
+			second
+		});
+
+		assert_eq!(client.recv_message().unwrap(), Message::text("welcome back"));
+		let mut second = remote.join().unwrap();
+		client.close().unwrap();
+		let goodbye: Message = second.recv_message().unwrap();
+		assert_eq!(goodbye, Message::close());
+		assert!(client.recv_message().is_err());
+
+		assert_eq!(*events.lock().unwrap(),
+		           ["connecting", "failed", "connecting", "connected", "disconnected", "waiting",
+		            "connecting", "connected"]);
+	}
+
+	#[test]
+	fn backs_off_across_short_connections() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let waits = Arc::new(Mutex::new(Vec::new()));
+		let log = waits.clone();
+		let mut client = ReconnectingClient::insecure(ClientBuilder::new(&url).unwrap())
+			.backoff(Backoff::exponential(Duration::from_millis(1), Duration::from_millis(100))
+			             .jitter(0.0)
+			             .reset_after(Duration::from_secs(3600)))
+			.on_event(move |event: &ConnectionEvent| if let ConnectionEvent::Waiting(delay) = *event {
+			              log.lock().unwrap().push(delay);
+			          });
+
+		// every connection is closed right away
+		let remote = thread::spawn(move || for _ in 0..4 {
+		                               let mut client = server.accept().ok().unwrap().accept().unwrap();
+		                               client.send_message(&Message::close()).unwrap();
+		                               let _: WebSocketResult<Message> = client.recv_message();
+		                           });
+		client.connect().unwrap();
+		for _ in 0..3 {
+			let _: WebSocketResult<Message> = client.client().unwrap().recv_message();
+			client.disconnect(None);
+			client.connect().unwrap();
+		}
+		// the last close has to arrive before the connection goes away
+		let _: WebSocketResult<Message> = client.client().unwrap().recv_message();
+		drop(client);
+		remote.join().unwrap();
+
+		let millis = |ms| Duration::from_millis(ms);
+		assert_eq!(*waits.lock().unwrap(), [millis(1), millis(2), millis(4)]);
+	}
+}
@@ -0,0 +1,135 @@
+//! Which redirects a `ClientBuilder` follows during the handshake.
+use url::Url;
+
+use result::{WebSocketResult, WebSocketError};
+
+/// Rules for following redirects (`301`, `302`, `303`, `307` and `308`)
+/// the server answers the upgrade request with. Redirects aren't followed
+/// unless a policy is set with `ClientBuilder::redirect_policy`.
+///
+/// By default a policy only allows redirects to the same host that don't
+/// go from `wss://` to `ws://`.
+///
+/// ```rust,no_run
+/// # use websocket::ClientBuilder;
+/// use websocket::client::RedirectPolicy;
+///
+/// // the load balancer sends us to a regional host
+/// let client = ClientBuilder::new("ws://chat.example.com").unwrap()
+///     .redirect_policy(RedirectPolicy::limited(3).allow_cross_host(true))
+///     .connect_insecure()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedirectPolicy {
+	max_hops: usize,
+	allow_downgrade: bool,
+	allow_cross_host: bool,
+	keep_authorization: bool,
+}
+
+impl RedirectPolicy {
+	/// Follows at most `max_hops` redirects.
+	pub fn limited(max_hops: usize) -> Self {
+		RedirectPolicy {
+			max_hops: max_hops,
+			allow_downgrade: false,
+			allow_cross_host: false,
+			keep_authorization: false,
+		}
+	}
+
+	/// Whether to follow a redirect from `wss://` to `ws://`, giving up
+	/// on TLS for the rest of the connection.
+	pub fn allow_downgrade(mut self, allow: bool) -> Self {
+		self.allow_downgrade = allow;
+		self
+	}
+
+	/// Whether to follow a redirect to another host. The custom headers of
+	/// the builder, like cookies, are sent to that host as well, except for
+	/// `Authorization`.
+	pub fn allow_cross_host(mut self, allow: bool) -> Self {
+		self.allow_cross_host = allow;
+		self
+	}
+
+	/// Whether to keep sending the bearer token and a custom `Authorization`
+	/// header after a redirect to another scheme, host or port. Only for
+	/// servers that are trusted with each other's credentials.
+	pub fn keep_authorization(mut self, keep: bool) -> Self {
+		self.keep_authorization = keep;
+		self
+	}
+
+	/// Whether the `Authorization` meant for `from` may be sent to `to`,
+	/// which it may if both have the same scheme, host and port.
+	pub fn keeps_authorization(&self, from: &Url, to: &Url) -> bool {
+		self.keep_authorization ||
+		(from.scheme() == to.scheme() && from.host_str() == to.host_str() &&
+		 from.port_or_known_default() == to.port_or_known_default())
+	}
+
+	/// Fails unless the redirect from `from` to `to`, after `hops` were
+	/// already followed, may be followed.
+	pub fn check(&self, from: &Url, to: &Url, hops: usize) -> WebSocketResult<()> {
+		if hops >= self.max_hops {
+			return Err(WebSocketError::ResponseError("Too many redirects"));
+		}
+		if from.scheme() == "wss" && to.scheme() == "ws" && !self.allow_downgrade {
+			return Err(WebSocketError::ResponseError("Redirect from wss:// to ws:// is not allowed"));
+		}
+		if from.host_str() != to.host_str() && !self.allow_cross_host {
+			return Err(WebSocketError::ResponseError("Redirect to another host is not allowed"));
+		}
+		Ok(())
+	}
+}
+
+/// Where the `Location` of a redirect points, relative to `base`. HTTP
+/// URLs are taken to mean the WebSocket URL of the same resource.
+pub fn location(base: &Url, location: &str) -> WebSocketResult<Url> {
+	let mut url = try!(base.join(location));
+	let scheme = match url.scheme() {
+		"ws" | "http" => "ws",
+		"wss" | "https" => "wss",
+		_ => return Err(WebSocketError::ResponseError("Redirect to a URL that isn't a WebSocket URL")),
+	};
+	// both are special schemes, so this can't fail
+	let _ = url.set_scheme(scheme);
+	url.set_fragment(None);
+	Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn policy_rules() {
+		let from = Url::parse("wss://example.com/chat").unwrap();
+		let policy = RedirectPolicy::limited(2);
+
+		let same = location(&from, "/v2/chat").unwrap();
+		assert_eq!(same.as_str(), "wss://example.com/v2/chat");
+		assert!(policy.check(&from, &same, 1).is_ok());
+		assert!(policy.check(&from, &same, 2).is_err());
+
+		let downgrade = location(&from, "http://example.com/chat").unwrap();
+		assert_eq!(downgrade.scheme(), "ws");
+		assert!(policy.check(&from, &downgrade, 0).is_err());
+		assert!(policy.clone().allow_downgrade(true).check(&from, &downgrade, 0).is_ok());
+
+		let other = location(&from, "https://eu.example.com/chat").unwrap();
+		assert!(policy.check(&from, &other, 0).is_err());
+		assert!(policy.clone().allow_cross_host(true).check(&from, &other, 0).is_ok());
+
+		assert!(location(&from, "ftp://example.com/").is_err());
+
+		assert!(policy.keeps_authorization(&from, &same));
+		assert!(!policy.keeps_authorization(&from, &downgrade));
+		assert!(!policy.keeps_authorization(&from, &location(&from, "//example.com:8443/").unwrap()));
+		assert!(!policy.keeps_authorization(&from, &other));
+		assert!(policy.keep_authorization(true).keeps_authorization(&from, &other));
+	}
+}
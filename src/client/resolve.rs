@@ -0,0 +1,86 @@
+//! How a `ClientBuilder` finds the addresses of the host it connects to.
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+
+/// Looks up the addresses of a host, in place of the system resolver.
+///
+/// Closures taking the host and port implement this:
+///
+/// ```rust,no_run
+/// # use websocket::ClientBuilder;
+/// use std::io;
+/// use std::net::SocketAddr;
+///
+/// let client = ClientBuilder::new("ws://chat.service.internal/room").unwrap()
+///     .resolver(|host: &str, port: u16| -> io::Result<Vec<SocketAddr>> {
+///         // ask service discovery instead
+///         Ok(vec![SocketAddr::from(([10, 0, 3, 7], port))])
+///     })
+///     .connect_insecure()
+///     .unwrap();
+/// ```
+pub trait Resolve {
+	/// The addresses to try for `host` and `port`, in order. `host` is a
+	/// domain name or an IP address without brackets.
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+impl<F> Resolve for F
+	where F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>>
+{
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		self(host, port)
+	}
+}
+
+/// Resolves with the operating system, which is what a `ClientBuilder`
+/// does unless told otherwise. Useful for resolvers that only handle
+/// some hosts themselves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		Ok(try!((host, port).to_socket_addrs()).collect())
+	}
+}
+
+/// Where a `ClientBuilder` gets addresses from.
+#[derive(Clone)]
+pub enum Resolution {
+	/// The system resolver.
+	System,
+	/// These addresses whatever the host.
+	Fixed(Vec<SocketAddr>),
+	/// A resolver of the user.
+	Custom(Arc<Resolve + Send + Sync>),
+}
+
+impl Resolution {
+	/// The addresses to try for `host` and `port`.
+	pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		match *self {
+			Resolution::System => SystemResolver.resolve(host, port),
+			Resolution::Fixed(ref addrs) => Ok(addrs.clone()),
+			Resolution::Custom(ref resolver) => resolver.resolve(host, port),
+		}
+	}
+}
+
+impl Default for Resolution {
+	fn default() -> Self {
+		Resolution::System
+	}
+}
+
+impl fmt::Debug for Resolution {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Resolution::System => fmt.write_str("System"),
+			Resolution::Fixed(ref addrs) => fmt.debug_tuple("Fixed").field(addrs).finish(),
+			Resolution::Custom(_) => fmt.write_str("Custom"),
+		}
+	}
+}
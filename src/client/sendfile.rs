@@ -0,0 +1,286 @@
+//! Sends the contents of a file as the payload of binary data frames.
+//!
+//! Server frames are never masked, so when the client is in the server role
+//! and its stream is a plain `TcpStream` the payload is handed straight from
+//! the file descriptor to the socket with `sendfile(2)` on Linux.
+//! Every other case (masked frames, SSL, unix sockets, boxed streams...)
+//! falls back to reading the file into a buffer and writing it out.
+use std::any::Any;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+
+use dataframe::Opcode;
+use result::{WebSocketResult, WebSocketError};
+use ws::util::header::{self as dfh, DataFrameHeader};
+use ws::util::mask;
+
+/// Size of the chunks read from a file when the payload has to be copied
+/// through userspace.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writes the bytes `range` of `file` as a binary message split into
+/// data frames no longer than `fragment_size`. A nonblocking stream that
+/// would block is an error that isn't `WouldBlock`, since the frame it
+/// happened in is left half written.
+pub fn send_file<W>(
+	stream: &mut W,
+	masked: bool,
+	file: &File,
+	range: Range<u64>,
+	fragment_size: u64,
+) -> WebSocketResult<()>
+	where W: Write + Any
+{
+	if range.start > range.end {
+		return Err(WebSocketError::DataFrameError("Invalid file range"));
+	}
+	if fragment_size == 0 {
+		return Err(WebSocketError::DataFrameError("Fragment size must not be zero"));
+	}
+
+	let mut offset = range.start;
+	let mut opcode = Opcode::Binary;
+
+	loop {
+		let len = cmp::min(fragment_size, range.end - offset);
+		let finished = offset + len == range.end;
+
+		let mut flags = dfh::DataFrameFlags::empty();
+		if finished {
+			flags.insert(dfh::FIN);
+		}
+		let mask = if masked { Some(mask::gen_mask()) } else { None };
+
+		let mut header = Vec::with_capacity(14);
+		try!(dfh::write_header(&mut header,
+		                       DataFrameHeader {
+		                           flags: flags,
+		                           opcode: opcode as u8,
+		                           mask: mask,
+		                           len: len,
+		                       }));
+		let written = stream.write_all(&header).and_then(|_| match mask {
+			Some(key) => copy_file(stream, |buf, at| read_at(file, buf, at), offset, len, Some(key)),
+			None => transfer_file(stream, file, offset, len),
+		});
+		try!(written.map_err(in_frame));
+
+		if finished {
+			return Ok(());
+		}
+
+		offset += len;
+		opcode = Opcode::Continuation;
+	}
+}
+
+/// Sends `len` bytes of `file` starting at `offset` without masking,
+/// using a zero copy transfer if the stream allows it.
+fn transfer_file<W>(stream: &mut W, file: &File, offset: u64, len: u64) -> io::Result<()>
+	where W: Write + Any
+{
+	let sent = match (stream as &mut Any).downcast_mut::<TcpStream>() {
+		Some(tcp) => try!(zero_copy(tcp, file, offset, len)),
+		None => 0,
+	};
+
+	copy_file(stream, |buf, at| read_at(file, buf, at), offset + sent, len - sent, None)
+}
+
+/// An error writing a frame, which can't be retried if the stream would
+/// block since part of the frame may have been written.
+fn in_frame(e: io::Error) -> io::Error {
+	if e.kind() == io::ErrorKind::WouldBlock {
+		io::Error::new(io::ErrorKind::Other, "the stream would block in the middle of a frame")
+	} else {
+		e
+	}
+}
+
+/// Transfers as much as possible with `sendfile(2)`, returning how many bytes
+/// were sent. If the kernel refuses the transfer before anything was sent
+/// the caller should copy the rest by hand.
+#[cfg(target_os = "linux")]
+fn zero_copy(stream: &TcpStream, file: &File, offset: u64, len: u64) -> io::Result<u64> {
+	use std::os::unix::io::AsRawFd;
+	use libc;
+
+	// sendfile transfers at most 0x7ffff000 bytes per call
+	const MAX_CHUNK: u64 = 0x7fff_f000;
+
+	let mut sent = 0;
+	while sent < len {
+		let mut off = (offset + sent) as libc::off_t;
+		let count = cmp::min(len - sent, MAX_CHUNK) as usize;
+		let ret = unsafe { libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &mut off, count) };
+
+		if ret < 0 {
+			let err = io::Error::last_os_error();
+			match err.raw_os_error() {
+				Some(libc::EINTR) => continue,
+				Some(libc::EINVAL) | Some(libc::ENOSYS) if sent == 0 => return Ok(0),
+				_ => return Err(err),
+			}
+		}
+		if ret == 0 {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file is shorter than the requested range"));
+		}
+		sent += ret as u64;
+	}
+	Ok(sent)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn zero_copy(_: &TcpStream, _: &File, _: u64, _: u64) -> io::Result<u64> {
+	Ok(0)
+}
+
+/// Copies `len` bytes of a file starting at `offset` through a buffer,
+/// masking them with `key` if there is one. `read_at` reads the file at an
+/// offset, possibly less than asked for.
+fn copy_file<W, R>(
+	stream: &mut W,
+	mut read_at: R,
+	offset: u64,
+	len: u64,
+	key: Option<[u8; 4]>,
+) -> io::Result<()>
+	where W: Write,
+	      R: FnMut(&mut [u8], u64) -> io::Result<usize>
+{
+	let mut buf = vec![0; cmp::min(len, COPY_BUFFER_SIZE as u64) as usize];
+	let mut copied = 0;
+
+	while copied < len {
+		let want = cmp::min(len - copied, buf.len() as u64) as usize;
+		let read = match read_at(&mut buf[..want], offset + copied) {
+			Ok(0) => {
+				return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+				                          "file is shorter than the requested range"))
+			}
+			Ok(n) => n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		};
+
+		match key {
+			// reads can be short, so the key is turned to where the chunk starts
+			Some(key) => {
+				let at = (copied % 4) as usize;
+				let key = [key[at], key[(at + 1) % 4], key[(at + 2) % 4], key[(at + 3) % 4]];
+				try!(stream.write_all(&mask::mask_data(key, &buf[..read])))
+			}
+			None => try!(stream.write_all(&buf[..read])),
+		}
+		copied += read as u64;
+	}
+	Ok(())
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+	use std::os::unix::fs::FileExt;
+	file.read_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+	use std::os::windows::fs::FileExt;
+	file.seek_read(buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::io::Write;
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+	use dataframe::DataFrame;
+
+	fn temp_file(name: &str, contents: &[u8]) -> File {
+		let path = env::temp_dir().join(format!("websocket-sendfile-{}-{}", name, ::std::process::id()));
+		fs::File::create(&path).unwrap().write_all(contents).unwrap();
+		let file = File::open(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		file
+	}
+
+	#[test]
+	fn send_file_over_tcp() {
+		let contents: Vec<u8> = (0..300_000).map(|i| i as u8).collect();
+		let file = temp_file("tcp", &contents);
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let reader = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			let mut frames = Vec::new();
+			loop {
+				let frame = DataFrame::read_dataframe(&mut stream, false).unwrap();
+				let finished = frame.finished;
+				frames.push(frame);
+				if finished {
+					return frames;
+				}
+			}
+		});
+
+		let mut stream = listener.accept().unwrap().0;
+		send_file(&mut stream, false, &file, 1000..250_000, 100_000).unwrap();
+
+		let frames = reader.join().unwrap();
+		assert_eq!(frames.len(), 3);
+		assert_eq!(frames[0].opcode, Opcode::Binary);
+		assert_eq!(frames[1].opcode, Opcode::Continuation);
+		assert_eq!(frames[2].opcode, Opcode::Continuation);
+
+		let payload: Vec<u8> = frames.into_iter().flat_map(|f| f.data).collect();
+		assert_eq!(&payload[..], &contents[1000..250_000]);
+	}
+
+	#[test]
+	fn send_file_masked() {
+		let contents = b"the quick brown fox jumps over the lazy dog";
+		let file = temp_file("masked", contents);
+
+		let mut buf = Vec::new();
+		send_file(&mut buf, true, &file, 4..19, 1 << 20).unwrap();
+
+		let frame = DataFrame::read_dataframe(&mut &buf[..], true).unwrap();
+		assert!(frame.finished);
+		assert_eq!(frame.opcode, Opcode::Binary);
+		assert_eq!(&frame.data[..], b"quick brown fox");
+	}
+
+	#[test]
+	fn masks_across_short_reads() {
+		let contents = b"the quick brown fox jumps over the lazy dog";
+		let key = [1, 2, 3, 4];
+		let mut buf = Vec::new();
+		// three bytes at a time, so chunks start all over the key
+		copy_file(&mut buf,
+		          |buf: &mut [u8], at| {
+			          let n = cmp::min(3, buf.len());
+			          buf[..n].copy_from_slice(&contents[at as usize..at as usize + n]);
+			          Ok(n)
+			         },
+		          0,
+		          contents.len() as u64,
+		          Some(key))
+			.unwrap();
+		assert_eq!(buf, mask::mask_data(key, contents));
+	}
+
+	#[test]
+	fn send_file_past_end() {
+		let file = temp_file("short", b"tiny");
+
+		let mut buf = Vec::new();
+		assert!(send_file(&mut buf, false, &file, 0..10, 1 << 20).is_err());
+	}
+}
@@ -0,0 +1,382 @@
+//! Socket options made on a `ClientBuilder`, applied to the TCP socket
+//! before it connects, and how the connection is made when the host has
+//! several addresses.
+use std::cmp;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use socket2::{Socket, Domain, Type, Protocol, TcpKeepalive};
+
+use result::{ConnectError, WebSocketResult, WebSocketError};
+
+/// How long to wait for a connection attempt before starting the next one
+/// in parallel, the value recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY_MS: u64 = 250;
+
+/// Everything a `ClientBuilder` was told about its TCP connection.
+#[derive(Clone, Debug)]
+pub struct TcpOptions {
+	/// The address to connect from.
+	pub local_address: Option<SocketAddr>,
+	/// Whether to send keepalive probes, and after how long idle if set.
+	pub keepalive: Option<Option<Duration>>,
+	/// `SO_SNDBUF`
+	pub send_buffer_size: Option<usize>,
+	/// `SO_RCVBUF`
+	pub recv_buffer_size: Option<usize>,
+	/// `TCP_NODELAY`
+	pub nodelay: Option<bool>,
+	/// The longest a single connection attempt may take.
+	pub connect_timeout: Option<Duration>,
+	/// The longest connecting, the TLS handshake and the HTTP upgrade may
+	/// take together.
+	pub handshake_timeout: Option<Duration>,
+	/// How long to wait before racing the next address, they are tried one
+	/// after the other if unset.
+	pub attempt_delay: Option<Duration>,
+}
+
+impl Default for TcpOptions {
+	fn default() -> Self {
+		TcpOptions {
+			local_address: None,
+			keepalive: None,
+			send_buffer_size: None,
+			recv_buffer_size: None,
+			nodelay: None,
+			connect_timeout: None,
+			handshake_timeout: None,
+			attempt_delay: Some(Duration::from_millis(CONNECTION_ATTEMPT_DELAY_MS)),
+		}
+	}
+}
+
+impl TcpOptions {
+	/// Connects to `addr` with these options, giving up after `timeout`.
+	pub fn connect(&self, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+		let socket = try!(Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP)));
+		if let Some(local) = self.local_address {
+			try!(socket.bind(&local.into()));
+		}
+		match self.keepalive {
+			Some(Some(idle)) => try!(socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))),
+			Some(None) => try!(socket.set_keepalive(true)),
+			None => (),
+		}
+		if let Some(size) = self.send_buffer_size {
+			try!(socket.set_send_buffer_size(size));
+		}
+		if let Some(size) = self.recv_buffer_size {
+			try!(socket.set_recv_buffer_size(size));
+		}
+		if let Some(nodelay) = self.nodelay {
+			try!(socket.set_nodelay(nodelay));
+		}
+		match timeout {
+			Some(timeout) => try!(socket.connect_timeout(&addr.into(), timeout)),
+			None => try!(socket.connect(&addr.into())),
+		}
+		Ok(socket.into())
+	}
+
+	/// Whether a connection to `addr` can be made from the local address,
+	/// they have to be the same IP version.
+	pub fn can_reach(&self, addr: &SocketAddr) -> bool {
+		match self.local_address {
+			Some(local) => local.is_ipv4() == addr.is_ipv4(),
+			None => true,
+		}
+	}
+
+	/// Connects to one of `addrs` before `deadline`. The addresses are
+	/// tried alternating between IPv6 and IPv4, and with an attempt delay
+	/// set a new attempt is started whenever the last one is taking longer
+	/// than that, the first connection made wins (RFC 8305). Every attempt
+	/// gets the connect timeout or what is left until `deadline`, and the
+	/// threads of parallel attempts only end with their attempt.
+	pub fn connect_any(
+		&self,
+		addrs: Vec<SocketAddr>,
+		deadline: Option<Instant>,
+	) -> Result<TcpStream, ConnectError> {
+		let addrs = interleave(addrs.into_iter().filter(|addr| self.can_reach(addr)).collect());
+		match self.attempt_delay {
+			Some(delay) if addrs.len() > 1 => self.race(addrs, delay, deadline),
+			_ => self.one_by_one(addrs, deadline),
+		}
+	}
+
+	fn one_by_one(
+		&self,
+		addrs: Vec<SocketAddr>,
+		deadline: Option<Instant>,
+	) -> Result<TcpStream, ConnectError> {
+		let mut attempts = Vec::new();
+		for addr in addrs {
+			match self.attempt_timeout(deadline).and_then(|timeout| self.connect(addr, timeout)) {
+				Ok(stream) => return Ok(stream),
+				Err(e) => attempts.push((addr, e)),
+			}
+		}
+		Err(ConnectError { attempts: attempts })
+	}
+
+	fn race(
+		&self,
+		addrs: Vec<SocketAddr>,
+		delay: Duration,
+		deadline: Option<Instant>,
+	) -> Result<TcpStream, ConnectError> {
+		let (sender, receiver) = mpsc::channel();
+		let mut waiting = addrs.into_iter();
+		let mut running = Vec::new();
+		let mut attempts = Vec::new();
+		let mut next_attempt = Instant::now();
+
+		loop {
+			if Instant::now() >= next_attempt {
+				if let Some(addr) = waiting.next() {
+					match self.attempt_timeout(deadline) {
+						Ok(timeout) => {
+							let options = self.clone();
+							let sender = sender.clone();
+							// a connection made after another one won is just dropped
+							thread::spawn(move || {
+								              let _ = sender.send((addr, options.connect(addr, timeout)));
+								             });
+							running.push(addr);
+							next_attempt = Instant::now() + delay;
+						}
+						Err(e) => attempts.push((addr, e)),
+					}
+				}
+			}
+			if running.is_empty() && waiting.len() == 0 {
+				return Err(ConnectError { attempts: attempts });
+			}
+
+			// wait for an attempt to finish until the next one is due
+			let until = match (deadline, waiting.len() > 0) {
+				(Some(deadline), true) => Some(cmp::min(deadline, next_attempt)),
+				(Some(deadline), false) => Some(deadline),
+				(None, true) => Some(next_attempt),
+				(None, false) => None,
+			};
+			let finished = match until {
+				Some(until) => {
+					let now = Instant::now();
+					if until > now {
+						receiver.recv_timeout(until - now).ok()
+					} else {
+						None
+					}
+				}
+				None => receiver.recv().ok(),
+			};
+
+			match finished {
+				Some((_, Ok(stream))) => return Ok(stream),
+				Some((addr, Err(e))) => {
+					if let Some(i) = running.iter().position(|a| *a == addr) {
+						running.remove(i);
+					}
+					attempts.push((addr, e));
+					// no need to wait for the delay after a failure
+					next_attempt = Instant::now();
+				}
+				None if deadline.map_or(false, |deadline| Instant::now() >= deadline) => {
+					for addr in running.into_iter().chain(waiting) {
+						attempts.push((addr, timed_out()));
+					}
+					return Err(ConnectError { attempts: attempts });
+				}
+				None => (),
+			}
+		}
+	}
+
+	/// How long the next attempt may take, failing once `deadline` passed.
+	fn attempt_timeout(&self, deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+		let remaining = match deadline {
+			Some(deadline) => {
+				let now = Instant::now();
+				if now >= deadline {
+					return Err(timed_out());
+				}
+				Some(deadline - now)
+			}
+			None => None,
+		};
+		Ok(match (self.connect_timeout, remaining) {
+		       (Some(timeout), Some(remaining)) => Some(cmp::min(timeout, remaining)),
+		       (timeout, remaining) => timeout.or(remaining),
+		   })
+	}
+}
+
+/// Orders addresses alternating between IP versions, starting with the
+/// version of the first one, otherwise keeping the order of the resolver.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+	let first_v6 = match addrs.first() {
+		Some(addr) => addr.is_ipv6(),
+		None => return addrs,
+	};
+	let (mut first, mut second): (Vec<_>, Vec<_>) =
+		addrs.into_iter().partition(|addr| addr.is_ipv6() == first_v6);
+	let mut ordered = Vec::with_capacity(first.len() + second.len());
+	first.reverse();
+	second.reverse();
+	loop {
+		match (first.pop(), second.pop()) {
+			(None, None) => return ordered,
+			(a, b) => ordered.extend(a.into_iter().chain(b)),
+		}
+	}
+}
+
+fn timed_out() -> io::Error {
+	io::Error::new(io::ErrorKind::TimedOut, "connection timed out")
+}
+
+/// The end of the time a handshake may take. Once the connection is made
+/// each read and write on its socket is limited to what is left, so a TLS
+/// handshake or upgrade response that doesn't come fails in time.
+#[derive(Debug)]
+pub struct Deadline {
+	at: Option<Instant>,
+	socket: Option<TcpStream>,
+	// the read and write timeouts of the socket before the handshake
+	timeouts: (Option<Duration>, Option<Duration>),
+}
+
+impl Deadline {
+	/// A deadline `timeout` from now, or none at all.
+	pub fn after(timeout: Option<Duration>) -> Deadline {
+		Deadline {
+			at: timeout.map(|timeout| Instant::now() + timeout),
+			socket: None,
+			timeouts: (None, None),
+		}
+	}
+
+	/// When the time is up.
+	pub fn at(&self) -> Option<Instant> {
+		self.at
+	}
+
+	/// Starts limiting the reads and writes of the connected `stream`.
+	pub fn start(&mut self, stream: &TcpStream) -> io::Result<()> {
+		if self.at.is_some() {
+			self.timeouts = (try!(stream.read_timeout()), try!(stream.write_timeout()));
+			self.socket = Some(try!(stream.try_clone()));
+		}
+		self.limit()
+	}
+
+	/// Passes on the result of a step of the handshake, as a timeout if
+	/// it failed after the time was up.
+	pub fn within<T>(&self, result: WebSocketResult<T>) -> WebSocketResult<T> {
+		match result {
+			Ok(value) => {
+				try!(self.limit());
+				Ok(value)
+			}
+			Err(_) if self.passed() => Err(WebSocketError::IoError(handshake_timed_out())),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Passes on the result of the last step of the handshake, putting
+	/// back the timeouts the socket had before if it succeeded.
+	pub fn finish<T>(self, result: WebSocketResult<T>) -> WebSocketResult<T> {
+		let value = try!(self.within(result));
+		if let Some(ref socket) = self.socket {
+			try!(socket.set_read_timeout(self.timeouts.0));
+			try!(socket.set_write_timeout(self.timeouts.1));
+		}
+		Ok(value)
+	}
+
+	fn passed(&self) -> bool {
+		self.at.map_or(false, |at| Instant::now() >= at)
+	}
+
+	fn limit(&self) -> io::Result<()> {
+		if let (Some(at), Some(ref socket)) = (self.at, self.socket.as_ref()) {
+			let now = Instant::now();
+			if now >= at {
+				return Err(handshake_timed_out());
+			}
+			try!(socket.set_read_timeout(Some(at - now)));
+			try!(socket.set_write_timeout(Some(at - now)));
+		}
+		Ok(())
+	}
+}
+
+fn handshake_timed_out() -> io::Error {
+	io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+
+	#[test]
+	fn interleaves_ip_versions() {
+		let addrs: Vec<SocketAddr> = vec!["[::1]:1".parse().unwrap(),
+		                                  "[::2]:1".parse().unwrap(),
+		                                  "[::3]:1".parse().unwrap(),
+		                                  "127.0.0.1:1".parse().unwrap()];
+		let ordered = interleave(addrs.clone());
+		assert_eq!(ordered, vec![addrs[0], addrs[3], addrs[1], addrs[2]]);
+
+		let v4_first = vec![addrs[3], addrs[0], addrs[1]];
+		assert_eq!(interleave(v4_first.clone()), v4_first);
+	}
+
+	#[test]
+	fn reports_every_address_tried() {
+		// nothing listens on the ports these listeners had
+		let closed: Vec<SocketAddr> = (0..2)
+			.map(|_| TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap())
+			.collect();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let open = listener.local_addr().unwrap();
+
+		let raced = TcpOptions::default();
+		let mut one_by_one = TcpOptions::default();
+		one_by_one.attempt_delay = None;
+		for options in &[raced, one_by_one] {
+			let stream = options.connect_any(vec![closed[0], closed[1], open], None).unwrap();
+			assert_eq!(stream.peer_addr().unwrap(), open);
+
+			let error = options.connect_any(closed.clone(), None).unwrap_err();
+			let mut tried: Vec<_> = error.attempts.iter().map(|&(addr, _)| addr).collect();
+			tried.sort();
+			let mut closed = closed.clone();
+			closed.sort();
+			assert_eq!(tried, closed);
+			assert!(!error.is_timeout());
+			assert!(error.to_string().contains(&closed[1].to_string()));
+		}
+	}
+
+	#[test]
+	fn deadline_restores_timeouts() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(7))).unwrap();
+
+		let mut deadline = Deadline::after(Some(Duration::from_secs(1)));
+		deadline.start(&stream).unwrap();
+		assert!(stream.read_timeout().unwrap() <= Some(Duration::from_secs(1)));
+		assert!(stream.write_timeout().unwrap().is_some());
+		deadline.finish(Ok(())).unwrap();
+		assert_eq!(stream.read_timeout().unwrap(), Some(Duration::from_secs(7)));
+		assert_eq!(stream.write_timeout().unwrap(), None);
+	}
+}
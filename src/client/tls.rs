@@ -0,0 +1,287 @@
+//! TLS settings made on a `ClientBuilder`.
+//!
+//! The same settings are turned into an OpenSSL `SslConnector` or a rustls
+//! `ClientConfig`, whichever the connection is made with. Pins are checked
+//! after the handshake on the certificates the server presented, so they
+//! also apply when a connector or config is passed in by hand.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(feature="rustls")]
+use std::sync::Arc;
+
+#[cfg(feature="ssl")]
+use openssl::ssl::{SslMethod, SslConnector, SslConnectorBuilder, SSL_VERIFY_NONE};
+#[cfg(feature="ssl")]
+use openssl::x509::X509;
+#[cfg(feature="ssl")]
+use openssl::pkey::PKey;
+#[cfg(feature="rustls")]
+use rustls;
+#[cfg(feature="rustls")]
+use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+#[cfg(feature="rustls")]
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+#[cfg(feature="rustls")]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+#[cfg(feature="rustls")]
+use rustls::pki_types::pem::PemObject;
+#[cfg(feature="rustls")]
+use webpki_roots;
+
+use result::{WebSocketResult, WebSocketError};
+use stream::TlsSession;
+
+/// A SHA-256 hash a certificate of the server has to match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pin {
+	/// The hash of the whole DER encoded certificate.
+	Certificate([u8; 32]),
+	/// The hash of the DER encoded SubjectPublicKeyInfo of the certificate,
+	/// which stays the same when a certificate is renewed with the same key.
+	PublicKey([u8; 32]),
+}
+
+/// Everything a `ClientBuilder` was told about TLS.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+	/// PEM files of CA certificates to trust besides the default ones.
+	pub roots: Vec<PathBuf>,
+	/// PEM files of the certificate chain and private key to present.
+	pub identity: Option<(PathBuf, PathBuf)>,
+	/// If not empty, one of the server's certificates has to match one of these.
+	pub pins: Vec<Pin>,
+	/// Skip verifying the server's certificate chain and name.
+	pub insecure: bool,
+}
+
+impl TlsOptions {
+	/// Fails unless one of the certificates in `session` matches a pin,
+	/// there is nothing to check if no pins were set.
+	pub fn check_pins(&self, session: Option<TlsSession>) -> WebSocketResult<()> {
+		if self.pins.is_empty() {
+			return Ok(());
+		}
+		let certificates = session.map(|s| s.peer_certificates).unwrap_or_default();
+		for certificate in &certificates {
+			let hash = sha256(certificate);
+			let key_hash = subject_public_key_info(certificate).ok().map(sha256);
+			for pin in &self.pins {
+				match *pin {
+					Pin::Certificate(ref pinned) if *pinned == hash => return Ok(()),
+					Pin::PublicKey(ref pinned) if Some(*pinned) == key_hash => return Ok(()),
+					_ => (),
+				}
+			}
+		}
+		Err(WebSocketError::TlsError("Server certificate matches none of the pins"))
+	}
+
+	/// Builds an OpenSSL connector with these settings.
+	#[cfg(feature="ssl")]
+	pub fn ssl_connector(&self) -> WebSocketResult<SslConnector> {
+		let mut builder = try!(SslConnectorBuilder::new(SslMethod::tls()));
+		for path in &self.roots {
+			let certificates = try!(X509::stack_from_pem(&try!(read_pem(path))));
+			for certificate in certificates {
+				try!(builder.cert_store_mut().add_cert(certificate));
+			}
+		}
+		if let Some((ref chain, ref key)) = self.identity {
+			let mut chain = try!(X509::stack_from_pem(&try!(read_pem(chain)))).into_iter();
+			match chain.next() {
+				Some(certificate) => try!(builder.set_certificate(&certificate)),
+				None => return Err(WebSocketError::TlsError("No certificate in the PEM file")),
+			}
+			for certificate in chain {
+				try!(builder.add_extra_chain_cert(certificate));
+			}
+			let key = try!(PKey::private_key_from_pem(&try!(read_pem(key))));
+			try!(builder.set_private_key(&key));
+			try!(builder.check_private_key());
+		}
+		if self.insecure {
+			builder.set_verify(SSL_VERIFY_NONE);
+		}
+		Ok(builder.build())
+	}
+
+	/// Builds a rustls configuration with these settings, trusting the
+	/// Mozilla root certificates as well as the added ones.
+	#[cfg(feature="rustls")]
+	pub fn rustls_config(&self) -> WebSocketResult<rustls::ClientConfig> {
+		let mut roots = rustls::RootCertStore::empty();
+		roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+		for path in &self.roots {
+			for certificate in try!(pem_certificates(path)) {
+				try!(roots.add(certificate));
+			}
+		}
+
+		let provider = Arc::new(rustls::crypto::ring::default_provider());
+		let builder = try!(rustls::ClientConfig::builder_with_provider(provider.clone())
+		                       .with_safe_default_protocol_versions())
+			.with_root_certificates(roots);
+		let mut config = match self.identity {
+			Some((ref chain, ref key)) => {
+				let key = try!(PrivateKeyDer::from_pem_slice(&try!(read_pem(key)))
+				                   .map_err(|_| WebSocketError::TlsError("No private key in the PEM file")));
+				try!(builder.with_client_auth_cert(try!(pem_certificates(chain)), key))
+			}
+			None => builder.with_no_client_auth(),
+		};
+		if self.insecure {
+			config.dangerous()
+			      .set_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)));
+		}
+		Ok(config)
+	}
+}
+
+fn read_pem(path: &Path) -> WebSocketResult<Vec<u8>> {
+	let mut pem = Vec::new();
+	try!(try!(File::open(path)).read_to_end(&mut pem));
+	Ok(pem)
+}
+
+#[cfg(feature="rustls")]
+fn pem_certificates(path: &Path) -> WebSocketResult<Vec<CertificateDer<'static>>> {
+	let pem = try!(read_pem(path));
+	let certificates = try!(CertificateDer::pem_slice_iter(&pem)
+	                            .collect::<Result<Vec<_>, _>>()
+	                            .map_err(|_| WebSocketError::TlsError("Invalid PEM file")));
+	if certificates.is_empty() {
+		return Err(WebSocketError::TlsError("No certificate in the PEM file"));
+	}
+	Ok(certificates)
+}
+
+/// Takes any certificate, but still checks the handshake is signed by the
+/// key in it so the connection is at least with the holder of that key.
+#[cfg(feature="rustls")]
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+#[cfg(feature="rustls")]
+impl ServerCertVerifier for AcceptAnyCertificate {
+	fn verify_server_cert(
+		&self,
+		_end_entity: &CertificateDer,
+		_intermediates: &[CertificateDer],
+		_server_name: &ServerName,
+		_ocsp_response: &[u8],
+		_now: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		Ok(ServerCertVerified::assertion())
+	}
+
+	fn verify_tls12_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer,
+		dss: &rustls::DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+	}
+
+	fn verify_tls13_signature(
+		&self,
+		message: &[u8],
+		cert: &CertificateDer,
+		dss: &rustls::DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+		self.0.signature_verification_algorithms.supported_schemes()
+	}
+}
+
+#[cfg(feature="ssl")]
+fn sha256(data: &[u8]) -> [u8; 32] {
+	::openssl::sha::sha256(data)
+}
+
+#[cfg(all(feature="rustls", not(feature="ssl")))]
+fn sha256(data: &[u8]) -> [u8; 32] {
+	let mut hash = [0; 32];
+	hash.copy_from_slice(::ring::digest::digest(&::ring::digest::SHA256, data).as_ref());
+	hash
+}
+
+/// Splits the first DER element off `der`, returning its tag, its contents
+/// and whatever follows it.
+fn der_element(der: &[u8]) -> Result<(u8, &[u8], &[u8]), ()> {
+	if der.len() < 2 {
+		return Err(());
+	}
+	let (len, start) = if der[1] < 0x80 {
+		(der[1] as usize, 2)
+	} else {
+		let count = (der[1] & 0x7f) as usize;
+		if count == 0 || count > 4 || der.len() < 2 + count {
+			return Err(());
+		}
+		let len = der[2..2 + count].iter().fold(0, |len, &byte| (len << 8) | byte as usize);
+		(len, 2 + count)
+	};
+	if der.len() - start < len {
+		return Err(());
+	}
+	Ok((der[0], &der[start..start + len], &der[start + len..]))
+}
+
+/// Finds the encoded SubjectPublicKeyInfo of an X.509 certificate.
+fn subject_public_key_info(certificate: &[u8]) -> Result<&[u8], ()> {
+	let (_, certificate, _) = try!(der_element(certificate));
+	let (_, mut tbs, _) = try!(der_element(certificate));
+	// the version is optional and tagged [0]
+	if tbs.first() == Some(&0xa0) {
+		tbs = try!(der_element(tbs)).2;
+	}
+	// serial number, signature algorithm, issuer, validity and subject
+	for _ in 0..5 {
+		tbs = try!(der_element(tbs)).2;
+	}
+	let (_, _, rest) = try!(der_element(tbs));
+	Ok(&tbs[..tbs.len() - rest.len()])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rcgen;
+
+	fn session_with(certificate: Vec<u8>) -> Option<TlsSession> {
+		Some(TlsSession {
+		         peer_certificates: vec![certificate],
+		         cipher: String::new(),
+		         protocol: String::new(),
+		     })
+	}
+
+	#[test]
+	fn certificate_and_public_key_pins() {
+		let key = rcgen::KeyPair::generate().unwrap();
+		let certificate = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+			.unwrap()
+			.self_signed(&key)
+			.unwrap();
+		let der = certificate.der().to_vec();
+		assert_eq!(subject_public_key_info(&der).unwrap(), &key.public_key_der()[..]);
+
+		let mut options = TlsOptions::default();
+		assert!(options.check_pins(None).is_ok());
+
+		options.pins.push(Pin::Certificate([0; 32]));
+		assert!(options.check_pins(session_with(der.clone())).is_err());
+		assert!(options.check_pins(None).is_err());
+
+		options.pins.push(Pin::PublicKey(sha256(&key.public_key_der())));
+		assert!(options.check_pins(session_with(der.clone())).is_ok());
+
+		options.pins = vec![Pin::Certificate(sha256(&der))];
+		assert!(options.check_pins(session_with(der)).is_ok());
+	}
+}
@@ -0,0 +1,587 @@
+//! The parts of HTTP/1.1 needed for a WebSocket opening handshake.
+//!
+//! Upgrade requests and responses are parsed with `httparse` into an
+//! `Incoming` message: the request line or status line, the HTTP version and
+//! a `Headers` map. Only the head of the message is read, anything after it
+//! is left in the `BufReader` to be used by the WebSocket connection.
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, BufRead};
+use std::str::{FromStr, Utf8Error};
+
+use httparse;
+use url::Url;
+
+use buffer::BufReader;
+use header::Headers;
+
+/// The maximum number of headers parsed from a handshake.
+pub const MAX_HEADERS: usize = 100;
+
+/// The result type of parsing an HTTP message.
+pub type HttpResult<T> = Result<T, HttpError>;
+
+/// Errors that can occur while parsing an HTTP handshake.
+#[derive(Debug)]
+pub enum HttpError {
+	/// An invalid `Method`, such as `GE,T`.
+	Method,
+	/// An invalid `RequestUri`, such as `exam ple.domain`.
+	Uri,
+	/// An invalid `HttpVersion`, such as `HTP/1.1`
+	Version,
+	/// An invalid `Header`.
+	Header,
+	/// A message head is too large to be reasonable.
+	TooLarge,
+	/// An invalid `Status`, such as `1337 ELITE`.
+	Status,
+	/// An `io::Error` that occurred while trying to read or write to a network stream.
+	Io(io::Error),
+	/// Parsing a field as string failed
+	Utf8(Utf8Error),
+}
+
+impl fmt::Display for HttpError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			HttpError::Io(ref e) => fmt::Display::fmt(e, f),
+			HttpError::Utf8(ref e) => fmt::Display::fmt(e, f),
+			ref e => f.write_str(e.description()),
+		}
+	}
+}
+
+impl Error for HttpError {
+	fn description(&self) -> &str {
+		match *self {
+			HttpError::Method => "Invalid Method specified",
+			HttpError::Uri => "Invalid Request URI specified",
+			HttpError::Version => "Invalid HTTP version specified",
+			HttpError::Header => "Invalid Header provided",
+			HttpError::TooLarge => "Message head is too large",
+			HttpError::Status => "Invalid Status provided",
+			HttpError::Io(ref e) => e.description(),
+			HttpError::Utf8(ref e) => e.description(),
+		}
+	}
+
+	fn cause(&self) -> Option<&Error> {
+		match *self {
+			HttpError::Io(ref error) => Some(error),
+			HttpError::Utf8(ref error) => Some(error),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for HttpError {
+	fn from(err: io::Error) -> HttpError {
+		HttpError::Io(err)
+	}
+}
+
+impl From<Utf8Error> for HttpError {
+	fn from(err: Utf8Error) -> HttpError {
+		HttpError::Utf8(err)
+	}
+}
+
+impl From<httparse::Error> for HttpError {
+	fn from(err: httparse::Error) -> HttpError {
+		match err {
+			httparse::Error::HeaderName |
+			httparse::Error::HeaderValue |
+			httparse::Error::NewLine |
+			httparse::Error::Token => HttpError::Header,
+			httparse::Error::Status => HttpError::Status,
+			httparse::Error::TooManyHeaders => HttpError::TooLarge,
+			httparse::Error::Version => HttpError::Version,
+		}
+	}
+}
+
+/// The Request Method (VERB)
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Method {
+	/// OPTIONS
+	Options,
+	/// GET
+	Get,
+	/// POST
+	Post,
+	/// PUT
+	Put,
+	/// DELETE
+	Delete,
+	/// HEAD
+	Head,
+	/// TRACE
+	Trace,
+	/// CONNECT
+	Connect,
+	/// PATCH
+	Patch,
+	/// Method extensions. An example would be `let m = Extension("FOO".to_string())`.
+	Extension(String),
+}
+
+impl FromStr for Method {
+	type Err = HttpError;
+
+	fn from_str(s: &str) -> HttpResult<Method> {
+		if s.is_empty() {
+			return Err(HttpError::Method);
+		}
+		Ok(match s {
+		       "OPTIONS" => Method::Options,
+		       "GET" => Method::Get,
+		       "POST" => Method::Post,
+		       "PUT" => Method::Put,
+		       "DELETE" => Method::Delete,
+		       "HEAD" => Method::Head,
+		       "TRACE" => Method::Trace,
+		       "CONNECT" => Method::Connect,
+		       "PATCH" => Method::Patch,
+		       _ => Method::Extension(s.to_owned()),
+		   })
+	}
+}
+
+impl fmt::Display for Method {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+		                Method::Options => "OPTIONS",
+		                Method::Get => "GET",
+		                Method::Post => "POST",
+		                Method::Put => "PUT",
+		                Method::Delete => "DELETE",
+		                Method::Head => "HEAD",
+		                Method::Trace => "TRACE",
+		                Method::Connect => "CONNECT",
+		                Method::Patch => "PATCH",
+		                Method::Extension(ref s) => s.as_ref(),
+		            })
+	}
+}
+
+/// Represents a version of the HTTP spec.
+#[derive(PartialEq, PartialOrd, Copy, Clone, Eq, Ord, Hash, Debug)]
+pub enum HttpVersion {
+	/// `HTTP/0.9`
+	Http09,
+	/// `HTTP/1.0`
+	Http10,
+	/// `HTTP/1.1`
+	Http11,
+	/// `HTTP/2.0`
+	Http20,
+	/// `HTTP/3.0`
+	Http30,
+}
+
+impl fmt::Display for HttpVersion {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+		                HttpVersion::Http09 => "HTTP/0.9",
+		                HttpVersion::Http10 => "HTTP/1.0",
+		                HttpVersion::Http11 => "HTTP/1.1",
+		                HttpVersion::Http20 => "HTTP/2.0",
+		                HttpVersion::Http30 => "HTTP/3.0",
+		            })
+	}
+}
+
+/// The Request-URI of a Request's StartLine.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RequestUri {
+	/// The most common request target, an absolute path and optional query.
+	///
+	/// For example, the line `GET /where?q=now HTTP/1.1` would parse the URI
+	/// as `AbsolutePath("/where?q=now".to_string())`.
+	AbsolutePath(String),
+	/// An absolute URI. Used in conjunction with proxies.
+	AbsoluteUri(Url),
+	/// The authority form is only for use with `CONNECT` requests.
+	Authority(String),
+	/// The star is used to target the entire server, instead of a specific resource.
+	Star,
+}
+
+impl RequestUri {
+	/// The path of the target, without the query.
+	pub fn path(&self) -> &str {
+		match *self {
+			RequestUri::AbsolutePath(ref target) => target.split('?').next().unwrap_or(""),
+			RequestUri::AbsoluteUri(ref url) => url.path(),
+			RequestUri::Authority(_) => "",
+			RequestUri::Star => "*",
+		}
+	}
+
+	/// The query of the target, if there is one, as it was sent.
+	pub fn query(&self) -> Option<&str> {
+		match *self {
+			RequestUri::AbsolutePath(ref target) => target.find('?').map(|i| &target[i + 1..]),
+			RequestUri::AbsoluteUri(ref url) => url.query(),
+			_ => None,
+		}
+	}
+}
+
+impl FromStr for RequestUri {
+	type Err = HttpError;
+
+	fn from_str(s: &str) -> HttpResult<RequestUri> {
+		let bytes = s.as_bytes();
+		if bytes.is_empty() {
+			Err(HttpError::Uri)
+		} else if bytes == b"*" {
+			Ok(RequestUri::Star)
+		} else if bytes.starts_with(b"/") {
+			Ok(RequestUri::AbsolutePath(s.to_owned()))
+		} else if bytes.contains(&b'/') {
+			Url::parse(s)
+				.map(RequestUri::AbsoluteUri)
+				.or(Err(HttpError::Uri))
+		} else {
+			Ok(RequestUri::Authority(s.to_owned()))
+		}
+	}
+}
+
+impl fmt::Display for RequestUri {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RequestUri::AbsolutePath(ref path) => f.write_str(path),
+			RequestUri::AbsoluteUri(ref url) => write!(f, "{}", url),
+			RequestUri::Authority(ref path) => f.write_str(path),
+			RequestUri::Star => f.write_str("*"),
+		}
+	}
+}
+
+macro_rules! status_codes {
+	($($(#[$doc:meta])* $name:ident = $code:expr, $reason:expr;)*) => {
+		/// An HTTP status code, only the codes that come up around
+		/// WebSocket handshakes are named.
+		#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+		pub enum StatusCode {
+			$($(#[$doc])* $name,)*
+			/// Any other status code
+			Unregistered(u16),
+		}
+
+		impl StatusCode {
+			/// Get the status code from its numeric value.
+			pub fn from_u16(n: u16) -> StatusCode {
+				match n {
+					$($code => StatusCode::$name,)*
+					_ => StatusCode::Unregistered(n),
+				}
+			}
+
+			/// The numeric value of this status code.
+			pub fn to_u16(&self) -> u16 {
+				match *self {
+					$(StatusCode::$name => $code,)*
+					StatusCode::Unregistered(n) => n,
+				}
+			}
+
+			/// Get the standardised `reason-phrase` for this status code.
+			pub fn canonical_reason(&self) -> Option<&'static str> {
+				match *self {
+					$(StatusCode::$name => Some($reason),)*
+					StatusCode::Unregistered(_) => None,
+				}
+			}
+		}
+	}
+}
+
+status_codes! {
+	/// 101 Switching Protocols
+	SwitchingProtocols = 101, "Switching Protocols";
+	/// 200 OK
+	Ok = 200, "OK";
+	/// 301 Moved Permanently
+	MovedPermanently = 301, "Moved Permanently";
+	/// 302 Found
+	Found = 302, "Found";
+	/// 303 See Other
+	SeeOther = 303, "See Other";
+	/// 307 Temporary Redirect
+	TemporaryRedirect = 307, "Temporary Redirect";
+	/// 308 Permanent Redirect
+	PermanentRedirect = 308, "Permanent Redirect";
+	/// 400 Bad Request
+	BadRequest = 400, "Bad Request";
+	/// 401 Unauthorized
+	Unauthorized = 401, "Unauthorized";
+	/// 403 Forbidden
+	Forbidden = 403, "Forbidden";
+	/// 404 Not Found
+	NotFound = 404, "Not Found";
+	/// 407 Proxy Authentication Required
+	ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+	/// 426 Upgrade Required
+	UpgradeRequired = 426, "Upgrade Required";
+	/// 429 Too Many Requests
+	TooManyRequests = 429, "Too Many Requests";
+	/// 500 Internal Server Error
+	InternalServerError = 500, "Internal Server Error";
+	/// 502 Bad Gateway
+	BadGateway = 502, "Bad Gateway";
+	/// 503 Service Unavailable
+	ServiceUnavailable = 503, "Service Unavailable";
+}
+
+impl fmt::Display for StatusCode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f,
+		       "{} {}",
+		       self.to_u16(),
+		       self.canonical_reason().unwrap_or("<unknown status code>"))
+	}
+}
+
+/// The raw status code and reason-phrase of a response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawStatus(pub u16, pub Cow<'static, str>);
+
+/// An Incoming Message head. Includes request/status line, and headers.
+#[derive(Debug, Clone)]
+pub struct Incoming<S> {
+	/// HTTP version of the message.
+	pub version: HttpVersion,
+	/// Subject (request line or status line) of Incoming message.
+	pub subject: S,
+	/// Headers of the Incoming message.
+	pub headers: Headers,
+}
+
+/// An incoming request head, the method and request target.
+pub type IncomingRequest = Incoming<(Method, RequestUri)>;
+
+/// An incoming response head, the status of the response.
+pub type IncomingResponse = Incoming<RawStatus>;
+
+fn version(minor: Option<u8>) -> HttpVersion {
+	match minor {
+		Some(0) => HttpVersion::Http10,
+		_ => HttpVersion::Http11,
+	}
+}
+
+fn headers(raw: &[httparse::Header]) -> Headers {
+	let mut headers = Headers::new();
+	for header in raw {
+		headers.append_raw(header.name, header.value.to_vec());
+	}
+	headers
+}
+
+/// Parses a request head from a buffered stream.
+pub fn parse_request<R>(reader: &mut BufReader<R>) -> HttpResult<IncomingRequest>
+	where R: Read
+{
+	parse(reader, |buf| {
+		let mut raw = [httparse::EMPTY_HEADER; MAX_HEADERS];
+		let mut req = httparse::Request::new(&mut raw);
+		match try!(req.parse(buf)) {
+			httparse::Status::Complete(len) => {
+				let method = try!(req.method.unwrap().parse());
+				let uri = try!(req.path.unwrap().parse());
+				Ok(Some((Incoming {
+				             version: version(req.version),
+				             subject: (method, uri),
+				             headers: headers(req.headers),
+				         },
+				         len)))
+			}
+			httparse::Status::Partial => Ok(None),
+		}
+	})
+}
+
+/// Parses a response head from a buffered stream.
+pub fn parse_response<R>(reader: &mut BufReader<R>) -> HttpResult<IncomingResponse>
+	where R: Read
+{
+	parse(reader, |buf| {
+		let mut raw = [httparse::EMPTY_HEADER; MAX_HEADERS];
+		let mut res = httparse::Response::new(&mut raw);
+		match try!(res.parse(buf)) {
+			httparse::Status::Complete(len) => {
+				let code = res.code.unwrap();
+				let reason = match StatusCode::from_u16(code).canonical_reason() {
+					Some(reason) if reason == res.reason.unwrap_or("") => Cow::Borrowed(reason),
+					_ => Cow::Owned(res.reason.unwrap_or("").to_owned()),
+				};
+				Ok(Some((Incoming {
+				             version: version(res.version),
+				             subject: RawStatus(code, reason),
+				             headers: headers(res.headers),
+				         },
+				         len)))
+			}
+			httparse::Status::Partial => Ok(None),
+		}
+	})
+}
+
+fn parse<R, T, F>(reader: &mut BufReader<R>, parse: F) -> HttpResult<T>
+	where R: Read,
+	      F: Fn(&[u8]) -> HttpResult<Option<(T, usize)>>
+{
+	loop {
+		if let Some((head, len)) = try!(parse(reader.get_buf())) {
+			reader.consume(len);
+			return Ok(head);
+		}
+		match try!(reader.read_into_buf()) {
+			0 if reader.get_buf().len() == ::buffer::MAX_BUFFER_SIZE => {
+				return Err(HttpError::TooLarge)
+			}
+			0 => {
+				return Err(HttpError::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+				                                        "early eof")))
+			}
+			_ => (),
+		}
+	}
+}
+
+#[cfg(feature = "hyper")]
+mod hyper_compat {
+	use hyper;
+	use super::{Method, HttpVersion, RequestUri};
+
+	impl From<hyper::method::Method> for Method {
+		fn from(method: hyper::method::Method) -> Method {
+			method.as_ref().parse().unwrap()
+		}
+	}
+
+	impl From<hyper::version::HttpVersion> for HttpVersion {
+		fn from(version: hyper::version::HttpVersion) -> HttpVersion {
+			match version {
+				hyper::version::HttpVersion::Http09 => HttpVersion::Http09,
+				hyper::version::HttpVersion::Http10 => HttpVersion::Http10,
+				hyper::version::HttpVersion::Http11 => HttpVersion::Http11,
+				hyper::version::HttpVersion::Http20 => HttpVersion::Http20,
+			}
+		}
+	}
+
+	impl From<hyper::uri::RequestUri> for RequestUri {
+		fn from(uri: hyper::uri::RequestUri) -> RequestUri {
+			match uri {
+				hyper::uri::RequestUri::AbsolutePath(p) => RequestUri::AbsolutePath(p),
+				hyper::uri::RequestUri::AbsoluteUri(u) => RequestUri::AbsoluteUri(u),
+				hyper::uri::RequestUri::Authority(a) => RequestUri::Authority(a),
+				hyper::uri::RequestUri::Star => RequestUri::Star,
+			}
+		}
+	}
+}
+
+#[cfg(feature = "http")]
+mod http_compat {
+	use http;
+	use super::{Method, HttpVersion, RequestUri};
+
+	impl<'a> From<&'a http::Method> for Method {
+		fn from(method: &'a http::Method) -> Method {
+			method.as_str().parse().unwrap()
+		}
+	}
+
+	impl From<http::Version> for HttpVersion {
+		fn from(version: http::Version) -> HttpVersion {
+			match version {
+				http::Version::HTTP_09 => HttpVersion::Http09,
+				http::Version::HTTP_10 => HttpVersion::Http10,
+				http::Version::HTTP_2 => HttpVersion::Http20,
+				http::Version::HTTP_3 => HttpVersion::Http30,
+				_ => HttpVersion::Http11,
+			}
+		}
+	}
+
+	impl From<HttpVersion> for http::Version {
+		fn from(version: HttpVersion) -> http::Version {
+			match version {
+				HttpVersion::Http09 => http::Version::HTTP_09,
+				HttpVersion::Http10 => http::Version::HTTP_10,
+				HttpVersion::Http11 => http::Version::HTTP_11,
+				HttpVersion::Http20 => http::Version::HTTP_2,
+				HttpVersion::Http30 => http::Version::HTTP_3,
+			}
+		}
+	}
+
+	impl<'a> From<&'a http::Uri> for RequestUri {
+		fn from(uri: &'a http::Uri) -> RequestUri {
+			uri.to_string()
+			   .parse()
+			   .unwrap_or_else(|_| RequestUri::AbsolutePath(uri.path().to_owned()))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Read};
+	use buffer::BufReader;
+	use header::{Host, WebSocketKey};
+
+	#[test]
+	fn parse_upgrade_request() {
+		let raw = b"GET /chat?room=1 HTTP/1.1\r\n\
+		            Host: example.com:8000\r\n\
+		            Upgrade: websocket\r\n\
+		            Connection: Upgrade\r\n\
+		            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		            Sec-WebSocket-Version: 13\r\n\r\n\x81\x00";
+		let mut reader = BufReader::new(Cursor::new(&raw[..]));
+		let request = parse_request(&mut reader).unwrap();
+
+		assert_eq!(request.version, HttpVersion::Http11);
+		assert_eq!(request.subject.0, Method::Get);
+		assert_eq!(request.subject.1,
+		           RequestUri::AbsolutePath("/chat?room=1".to_string()));
+		assert_eq!(request.headers.get::<Host>().unwrap().port, Some(8000));
+		assert_eq!(request.headers.get::<WebSocketKey>().unwrap().0,
+		           *b"the sample nonce");
+
+		// the frame after the head is left to be read
+		let mut rest = Vec::new();
+		reader.read_to_end(&mut rest).unwrap();
+		assert_eq!(rest, b"\x81\x00");
+	}
+
+	#[test]
+	fn parse_refusal_response() {
+		let raw = b"HTTP/1.1 401 Token Expired\r\nContent-Length: 0\r\n\r\n";
+		let mut reader = BufReader::new(Cursor::new(&raw[..]));
+		let response = parse_response(&mut reader).unwrap();
+
+		assert_eq!(response.subject, RawStatus(401, "Token Expired".into()));
+		assert_eq!(StatusCode::from_u16(response.subject.0),
+		           StatusCode::Unauthorized);
+	}
+
+	#[test]
+	fn parse_truncated_request() {
+		let raw = b"GET / HTTP/1.1\r\nHost: exam";
+		let mut reader = BufReader::new(Cursor::new(&raw[..]));
+		match parse_request(&mut reader) {
+			Err(HttpError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => (),
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+}
@@ -1,6 +1,6 @@
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::from_one_raw_str;
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::from_one_raw_str;
+use handshake::HttpResult;
 use std::fmt::{self, Debug};
 use std::str::FromStr;
 use serialize::base64::{ToBase64, FromBase64, STANDARD};
@@ -67,7 +67,7 @@ impl Header for WebSocketAccept {
 		"Sec-WebSocket-Accept"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketAccept> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<WebSocketAccept> {
 		from_one_raw_str(raw)
 	}
 }
@@ -84,7 +84,7 @@ mod tests {
 	use test;
 	use std::str::FromStr;
 	use header::{Headers, WebSocketKey};
-	use hyper::header::Header;
+	use header::Header;
 
 	#[test]
 	fn test_header_accept() {
@@ -0,0 +1,305 @@
+//! The general HTTP headers needed for a WebSocket handshake.
+
+use std::fmt;
+use std::str::{self, FromStr};
+use unicase::UniCase;
+
+use handshake::{HttpError, HttpResult};
+use header::{Header, HeaderFormat};
+use header::parsing::{from_one_raw_str, from_comma_delimited, fmt_comma_delimited};
+
+/// The `Host` header, the host and port of the resource being requested.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Host {
+	/// The hostname, such as example.com.
+	pub hostname: String,
+	/// An optional port number.
+	pub port: Option<u16>,
+}
+
+impl Header for Host {
+	fn header_name() -> &'static str {
+		"Host"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Host> {
+		from_one_raw_str(raw)
+	}
+}
+
+impl HeaderFormat for Host {
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.port {
+			None | Some(80) | Some(443) => f.write_str(&self.hostname[..]),
+			Some(port) => write!(f, "{}:{}", self.hostname, port),
+		}
+	}
+}
+
+impl FromStr for Host {
+	type Err = HttpError;
+
+	fn from_str(s: &str) -> HttpResult<Host> {
+		// IPv6 literals are enclosed in brackets, their colons aren't port separators
+		let port_sep = match s.rfind(']') {
+			Some(end) => s[end..].find(':').map(|i| i + end),
+			None => s.rfind(':'),
+		};
+		let (hostname, port) = match port_sep {
+			Some(i) => (&s[..i], Some(try!(s[i + 1..].parse().or(Err(HttpError::Header))))),
+			None => (s, None),
+		};
+		if hostname.is_empty() {
+			return Err(HttpError::Header);
+		}
+		Ok(Host {
+		       hostname: hostname.to_string(),
+		       port: port,
+		   })
+	}
+}
+
+/// Values that can be in the `Connection` header.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConnectionOption {
+	/// The `keep-alive` connection value.
+	KeepAlive,
+	/// The `close` connection value.
+	Close,
+	/// Values in the Connection header that are supposed to be names of other Headers,
+	/// like `upgrade`.
+	ConnectionHeader(UniCase<String>),
+}
+
+impl FromStr for ConnectionOption {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<ConnectionOption, ()> {
+		if UniCase(s) == UniCase("keep-alive") {
+			Ok(ConnectionOption::KeepAlive)
+		} else if UniCase(s) == UniCase("close") {
+			Ok(ConnectionOption::Close)
+		} else {
+			Ok(ConnectionOption::ConnectionHeader(UniCase(s.to_string())))
+		}
+	}
+}
+
+impl fmt::Display for ConnectionOption {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+		                ConnectionOption::KeepAlive => "keep-alive",
+		                ConnectionOption::Close => "close",
+		                ConnectionOption::ConnectionHeader(UniCase(ref s)) => s.as_ref(),
+		            })
+	}
+}
+
+/// The `Connection` header, which for a websocket handshake must contain `Upgrade`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Connection(pub Vec<ConnectionOption>);
+
+impl Header for Connection {
+	fn header_name() -> &'static str {
+		"Connection"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Connection> {
+		from_comma_delimited(raw).map(Connection)
+	}
+}
+
+impl HeaderFormat for Connection {
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt_comma_delimited(f, &self.0[..])
+	}
+}
+
+/// A protocol name used to identify a specific protocol. Names are case-sensitive
+/// except for the `WebSocket` value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtocolName {
+	/// `HTTP` value, Hypertext Transfer Protocol
+	Http,
+	/// `TLS` value, Transport Layer Security [RFC2817]
+	Tls,
+	/// `WebSocket` value, matched case insensitively, Web Socket Protocol [RFC6455]
+	WebSocket,
+	/// `h2c` value, HTTP/2 over cleartext TCP
+	H2c,
+	/// Any other protocol name
+	Unregistered(String),
+}
+
+impl FromStr for ProtocolName {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<ProtocolName, ()> {
+		Ok(match s {
+		       "HTTP" => ProtocolName::Http,
+		       "TLS" => ProtocolName::Tls,
+		       "h2c" => ProtocolName::H2c,
+		       _ => {
+			if UniCase(s) == UniCase("websocket") {
+				ProtocolName::WebSocket
+			} else {
+				ProtocolName::Unregistered(s.to_owned())
+			}
+		}
+		   })
+	}
+}
+
+impl fmt::Display for ProtocolName {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+		                ProtocolName::Http => "HTTP",
+		                ProtocolName::Tls => "TLS",
+		                ProtocolName::WebSocket => "websocket",
+		                ProtocolName::H2c => "h2c",
+		                ProtocolName::Unregistered(ref s) => s,
+		            })
+	}
+}
+
+/// Protocols that appear in the `Upgrade` header field
+#[derive(Clone, Debug, PartialEq)]
+pub struct Protocol {
+	/// The protocol identifier
+	pub name: ProtocolName,
+	/// The optional version of the protocol, often in the format "DIGIT.DIGIT" (e.g.. "1.2")
+	pub version: Option<String>,
+}
+
+impl Protocol {
+	/// Creates a new Protocol with the given name and version
+	pub fn new(name: ProtocolName, version: Option<String>) -> Protocol {
+		Protocol {
+			name: name,
+			version: version,
+		}
+	}
+}
+
+impl FromStr for Protocol {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Protocol, ()> {
+		let mut parts = s.splitn(2, '/');
+		Ok(Protocol::new(try!(parts.next().unwrap().parse()),
+		                 parts.next().map(|x| x.to_owned())))
+	}
+}
+
+impl fmt::Display for Protocol {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(fmt::Display::fmt(&self.name, f));
+		if let Some(ref version) = self.version {
+			try!(write!(f, "/{}", version));
+		}
+		Ok(())
+	}
+}
+
+/// The `Upgrade` header, listing the protocols the client wants to switch to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upgrade(pub Vec<Protocol>);
+
+impl Header for Upgrade {
+	fn header_name() -> &'static str {
+		"Upgrade"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Upgrade> {
+		from_comma_delimited(raw).map(Upgrade)
+	}
+}
+
+impl HeaderFormat for Upgrade {
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt_comma_delimited(f, &self.0[..])
+	}
+}
+
+/// The `Cookie` request header, holding `name=value` pairs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cookie(pub Vec<String>);
+
+impl Header for Cookie {
+	fn header_name() -> &'static str {
+		"Cookie"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Cookie> {
+		let mut cookies = Vec::with_capacity(raw.len());
+		for cookies_raw in raw.iter() {
+			let cookies_str = try!(str::from_utf8(&cookies_raw[..]));
+			for cookie_str in cookies_str.split(';') {
+				let cookie = cookie_str.trim();
+				if !cookie.is_empty() {
+					cookies.push(cookie.to_owned());
+				}
+			}
+		}
+
+		if cookies.is_empty() {
+			Err(HttpError::Header)
+		} else {
+			Ok(Cookie(cookies))
+		}
+	}
+}
+
+impl HeaderFormat for Cookie {
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0.join("; "))
+	}
+}
+
+/// The `Authorization` header, holding credentials exactly as they are sent.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Authorization(pub String);
+
+impl Header for Authorization {
+	fn header_name() -> &'static str {
+		"Authorization"
+	}
+
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Authorization> {
+		from_one_raw_str(raw).map(Authorization)
+	}
+}
+
+impl HeaderFormat for Authorization {
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_host() {
+		let host: Host = Header::parse_header(&[b"example.com:8080".to_vec()]).unwrap();
+		assert_eq!(host.hostname, "example.com");
+		assert_eq!(host.port, Some(8080));
+
+		let host: Host = Header::parse_header(&[b"[::1]".to_vec()]).unwrap();
+		assert_eq!(host.hostname, "[::1]");
+		assert_eq!(host.port, None);
+
+		let host: Host = Header::parse_header(&[b"[::1]:9001".to_vec()]).unwrap();
+		assert_eq!(host.hostname, "[::1]");
+		assert_eq!(host.port, Some(9001));
+	}
+
+	#[test]
+	fn parse_upgrade() {
+		let upgrade: Upgrade = Header::parse_header(&[b"WebSocket, h2c/2".to_vec()]).unwrap();
+		assert_eq!(upgrade.0,
+		           vec![Protocol::new(ProtocolName::WebSocket, None),
+		                Protocol::new(ProtocolName::H2c, Some("2".to_string()))]);
+	}
+}
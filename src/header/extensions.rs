@@ -1,8 +1,8 @@
 //! Provides the Sec-WebSocket-Extensions header.
 
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::{from_comma_delimited, fmt_comma_delimited};
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::{from_comma_delimited, fmt_comma_delimited};
+use handshake::HttpResult;
 use std::fmt;
 use std::str::FromStr;
 use std::ops::Deref;
@@ -54,7 +54,7 @@ impl FromStr for Extension {
 		           None => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
 		       },
 		       params: ext.map(|x| {
-			                       let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
+			                       let mut pair = x.splitn(2, '=').map(|x| x.trim().to_string());
 
 			                       Parameter {
 			                           name: pair.next().unwrap(),
@@ -111,7 +111,7 @@ impl Header for WebSocketExtensions {
 		"Sec-WebSocket-Extensions"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketExtensions> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<WebSocketExtensions> {
 		from_comma_delimited(raw).map(|vec| WebSocketExtensions(vec))
 	}
 }
@@ -132,7 +132,7 @@ impl fmt::Display for WebSocketExtensions {
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
-	use hyper::header::Header;
+	use header::Header;
 	use test;
 	#[test]
 	fn test_header_extensions() {
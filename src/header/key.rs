@@ -1,6 +1,6 @@
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::from_one_raw_str;
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::from_one_raw_str;
+use handshake::HttpResult;
 use std::fmt::{self, Debug};
 use rand;
 use std::mem;
@@ -63,7 +63,7 @@ impl Header for WebSocketKey {
 		"Sec-WebSocket-Key"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketKey> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<WebSocketKey> {
 		from_one_raw_str(raw)
 	}
 }
@@ -77,7 +77,7 @@ impl HeaderFormat for WebSocketKey {
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
-	use hyper::header::Header;
+	use header::Header;
 	use test;
 	#[test]
 	fn test_header_key() {
@@ -1,7 +1,19 @@
 //! Structs representing headers relevant in a WebSocket context.
 //!
 //! These headers are commonly used in WebSocket requests and responses.
-//! The `Header` trait from the `hyper` crate is used.
+//! Headers are stored in a `Headers` map, which keeps the raw bytes that were
+//! received and parses them into a typed `Header` the first time they are asked for.
+//!
+//! With the `hyper` feature enabled, `Headers` can be converted to and from
+//! hyper's own header map.
+
+use std::any::Any;
+use std::fmt;
+use std::iter::Extend;
+use std::slice;
+use std::sync::OnceLock;
+
+use handshake::HttpResult;
 
 pub use self::key::WebSocketKey;
 pub use self::accept::WebSocketAccept;
@@ -9,7 +21,7 @@ pub use self::protocol::WebSocketProtocol;
 pub use self::version::WebSocketVersion;
 pub use self::extensions::WebSocketExtensions;
 pub use self::origin::Origin;
-pub use hyper::header::*;
+pub use self::common::*;
 
 mod accept;
 mod key;
@@ -17,3 +29,485 @@ mod protocol;
 mod version;
 pub mod extensions;
 mod origin;
+mod common;
+pub mod parsing;
+
+/// A trait for any object that will represent a header field and value.
+///
+/// This trait represents the construction and identification of headers,
+/// and contains trait-object unsafe methods.
+pub trait Header: Clone + Any + Send + Sync {
+	/// Returns the name of the header field this belongs to.
+	///
+	/// This will become an associated constant once available.
+	fn header_name() -> &'static str;
+
+	/// Parse a header from a raw stream of bytes.
+	///
+	/// It's possible that a request can include a header field more than once,
+	/// and in that case, the slice will have a length greater than 1. However,
+	/// it's not necessarily the case that a Header is *allowed* to have more
+	/// than one field value. If that's the case, you **should** return an error
+	/// if `raw.len() > 1`.
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Self>;
+}
+
+/// A trait for any object that will represent a header field and value.
+///
+/// This trait represents the formatting of a `Header` for output to a TcpStream.
+pub trait HeaderFormat: fmt::Debug + HeaderClone + Send + Sync {
+	/// Format a header to be output into a TcpStream.
+	///
+	/// This method is not allowed to introduce an Err not produced
+	/// by the passed-in Formatter.
+	fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+#[doc(hidden)]
+pub trait HeaderClone {
+	fn clone_box(&self) -> Box<HeaderFormat>;
+	fn as_any(&self) -> &Any;
+	fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T> HeaderClone for T
+	where T: HeaderFormat + Clone + Any
+{
+	fn clone_box(&self) -> Box<HeaderFormat> {
+		Box::new(self.clone())
+	}
+
+	fn as_any(&self) -> &Any {
+		self
+	}
+
+	fn as_any_mut(&mut self) -> &mut Any {
+		self
+	}
+}
+
+impl Clone for Box<HeaderFormat> {
+	fn clone(&self) -> Box<HeaderFormat> {
+		self.clone_box()
+	}
+}
+
+/// Formats a `HeaderFormat` trait object through its `fmt_header` method.
+struct FmtHeader<'a>(&'a HeaderFormat);
+
+impl<'a> fmt::Display for FmtHeader<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt_header(f)
+	}
+}
+
+/// A single header field, holding its raw value and/or a typed representation.
+///
+/// At least one of the two is always present, the other one is created lazily.
+#[derive(Clone)]
+struct Item {
+	name: String,
+	raw: OnceLock<Vec<Vec<u8>>>,
+	typed: OnceLock<Option<Box<HeaderFormat>>>,
+}
+
+impl Item {
+	fn new_raw(name: String, raw: Vec<Vec<u8>>) -> Item {
+		let item = Item {
+			name: name,
+			raw: OnceLock::new(),
+			typed: OnceLock::new(),
+		};
+		let _ = item.raw.set(raw);
+		item
+	}
+
+	fn new_typed<H>(value: H) -> Item
+		where H: Header + HeaderFormat
+	{
+		let item = Item {
+			name: H::header_name().to_string(),
+			raw: OnceLock::new(),
+			typed: OnceLock::new(),
+		};
+		let _ = item.typed.set(Some(Box::new(value)));
+		item
+	}
+
+	fn raw(&self) -> &[Vec<u8>] {
+		self.raw.get_or_init(|| match self.typed.get() {
+			                     Some(&Some(ref typed)) => {
+				                     vec![FmtHeader(&**typed).to_string().into_bytes()]
+				                    }
+			                     _ => Vec::new(),
+			                    })
+	}
+
+	fn typed<H>(&self) -> Option<&H>
+		where H: Header + HeaderFormat
+	{
+		let typed = self.typed.get_or_init(|| {
+			H::parse_header(self.raw())
+				.ok()
+				.map(|h| Box::new(h) as Box<HeaderFormat>)
+		});
+		typed.as_ref().and_then(|t| t.as_any().downcast_ref::<H>())
+	}
+
+	fn typed_mut<H>(&mut self) -> Option<&mut H>
+		where H: Header + HeaderFormat
+	{
+		if self.typed::<H>().is_none() {
+			return None;
+		}
+		// the typed value is about to change, the raw value will be rebuilt from it
+		self.raw = OnceLock::new();
+		match self.typed.get_mut() {
+			Some(&mut Some(ref mut typed)) => typed.as_any_mut().downcast_mut::<H>(),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Item {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(&Some(ref typed)) = self.typed.get() {
+			if self.raw.get().is_none() {
+				return write!(f, "{}: {}", self.name, FmtHeader(&**typed));
+			}
+		}
+		for (i, line) in self.raw().iter().enumerate() {
+			if i > 0 {
+				try!(f.write_str("\r\n"));
+			}
+			try!(write!(f, "{}: {}", self.name, String::from_utf8_lossy(line)));
+		}
+		Ok(())
+	}
+}
+
+/// A map of header fields on requests and responses.
+///
+/// Field names are matched case-insensitively and the order in which
+/// they were first added is kept.
+#[derive(Clone, Default)]
+pub struct Headers {
+	items: Vec<Item>,
+}
+
+impl Headers {
+	/// Creates a new, empty headers map.
+	pub fn new() -> Headers {
+		Headers { items: Vec::new() }
+	}
+
+	fn position(&self, name: &str) -> Option<usize> {
+		self.items
+		    .iter()
+		    .position(|item| item.name.eq_ignore_ascii_case(name))
+	}
+
+	/// Set a header field to the corresponding value.
+	///
+	/// The field is determined by the type of the value being set.
+	pub fn set<H>(&mut self, value: H)
+		where H: Header + HeaderFormat
+	{
+		let item = Item::new_typed(value);
+		match self.position(H::header_name()) {
+			Some(i) => self.items[i] = item,
+			None => self.items.push(item),
+		}
+	}
+
+	/// Get a reference to the header field's value, if it exists.
+	pub fn get<H>(&self) -> Option<&H>
+		where H: Header + HeaderFormat
+	{
+		self.position(H::header_name())
+		    .and_then(|i| self.items[i].typed::<H>())
+	}
+
+	/// Get a mutable reference to the header field's value, if it exists.
+	pub fn get_mut<H>(&mut self) -> Option<&mut H>
+		where H: Header + HeaderFormat
+	{
+		match self.position(H::header_name()) {
+			Some(i) => self.items[i].typed_mut::<H>(),
+			None => None,
+		}
+	}
+
+	/// Returns a boolean of whether a certain header is in the map.
+	pub fn has<H>(&self) -> bool
+		where H: Header + HeaderFormat
+	{
+		self.position(H::header_name()).is_some()
+	}
+
+	/// Removes a header from the map, if one existed.
+	/// Returns true if a header has been removed.
+	pub fn remove<H>(&mut self) -> bool
+		where H: Header + HeaderFormat
+	{
+		self.remove_raw(H::header_name())
+	}
+
+	/// Access the raw value of a header.
+	///
+	/// ```rust
+	/// # use websocket::header::Headers;
+	/// let mut headers = Headers::new();
+	/// headers.set_raw("X-Request-Id", vec![b"42".to_vec()]);
+	///
+	/// assert_eq!(headers.get_raw("x-request-id"), Some(&[b"42".to_vec()][..]));
+	/// ```
+	pub fn get_raw(&self, name: &str) -> Option<&[Vec<u8>]> {
+		self.position(name).map(|i| self.items[i].raw())
+	}
+
+	/// Set the raw value of a header, bypassing any typed headers.
+	pub fn set_raw<K>(&mut self, name: K, value: Vec<Vec<u8>>)
+		where K: Into<String>
+	{
+		let item = Item::new_raw(name.into(), value);
+		match self.position(&item.name) {
+			Some(i) => self.items[i] = item,
+			None => self.items.push(item),
+		}
+	}
+
+	/// Append a value to the raw values of a header, this is how a header
+	/// that appears several times in a message (like `Set-Cookie`) is stored.
+	pub fn append_raw<K>(&mut self, name: K, value: Vec<u8>)
+		where K: Into<String>
+	{
+		let name = name.into();
+		match self.position(&name) {
+			Some(i) => {
+				let mut raw = self.items[i].raw().to_vec();
+				raw.push(value);
+				let name = self.items[i].name.clone();
+				self.items[i] = Item::new_raw(name, raw);
+			}
+			None => self.items.push(Item::new_raw(name, vec![value])),
+		}
+	}
+
+	/// Remove a header by name.
+	/// Returns true if a header has been removed.
+	pub fn remove_raw(&mut self, name: &str) -> bool {
+		match self.position(name) {
+			Some(i) => {
+				self.items.remove(i);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Returns an iterator over the header fields.
+	pub fn iter<'a>(&'a self) -> HeadersItems<'a> {
+		HeadersItems { inner: self.items.iter() }
+	}
+
+	/// Returns the number of headers in the map.
+	pub fn len(&self) -> usize {
+		self.items.len()
+	}
+
+	/// Remove all headers from the map.
+	pub fn clear(&mut self) {
+		self.items.clear();
+	}
+}
+
+impl PartialEq for Headers {
+	fn eq(&self, other: &Headers) -> bool {
+		self.len() == other.len() &&
+		self.iter()
+		    .all(|h| other.get_raw(h.name()) == Some(h.raw()))
+	}
+}
+
+impl fmt::Display for Headers {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for item in self.items.iter() {
+			try!(write!(f, "{}\r\n", item));
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for Headers {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map()
+		 .entries(self.iter().map(|h| (h.name(), h.value_string())))
+		 .finish()
+	}
+}
+
+/// An `Iterator` over the fields in a `Headers` map.
+pub struct HeadersItems<'a> {
+	inner: slice::Iter<'a, Item>,
+}
+
+impl<'a> Iterator for HeadersItems<'a> {
+	type Item = HeaderView<'a>;
+
+	fn next(&mut self) -> Option<HeaderView<'a>> {
+		self.inner.next().map(HeaderView)
+	}
+}
+
+/// Returned with the `HeadersItems` iterator.
+pub struct HeaderView<'a>(&'a Item);
+
+impl<'a> HeaderView<'a> {
+	/// Check if a HeaderView is a certain Header.
+	pub fn is<H>(&self) -> bool
+		where H: Header
+	{
+		self.0.name.eq_ignore_ascii_case(H::header_name())
+	}
+
+	/// Get the Header name as a slice.
+	pub fn name(&self) -> &'a str {
+		&self.0.name
+	}
+
+	/// Cast the value to a certain Header type.
+	pub fn value<H>(&self) -> Option<&'a H>
+		where H: Header + HeaderFormat
+	{
+		self.0.typed::<H>()
+	}
+
+	/// The raw values of this header.
+	pub fn raw(&self) -> &'a [Vec<u8>] {
+		self.0.raw()
+	}
+
+	/// Get just the header value as a String, multiple values are joined with a comma.
+	pub fn value_string(&self) -> String {
+		self.raw()
+		    .iter()
+		    .map(|line| String::from_utf8_lossy(line).into_owned())
+		    .collect::<Vec<String>>()
+		    .join(", ")
+	}
+}
+
+impl<'a> fmt::Display for HeaderView<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl<'a> Extend<HeaderView<'a>> for Headers {
+	fn extend<I>(&mut self, iter: I)
+		where I: IntoIterator<Item = HeaderView<'a>>
+	{
+		for header in iter {
+			let item = header.0.clone();
+			match self.position(&item.name) {
+				Some(i) => self.items[i] = item,
+				None => self.items.push(item),
+			}
+		}
+	}
+}
+
+#[cfg(feature = "hyper")]
+impl<'a> From<&'a ::hyper::header::Headers> for Headers {
+	fn from(headers: &'a ::hyper::header::Headers) -> Headers {
+		let mut converted = Headers::new();
+		for header in headers.iter() {
+			converted.set_raw(header.name(), vec![header.value_string().into_bytes()]);
+		}
+		converted
+	}
+}
+
+#[cfg(feature = "hyper")]
+impl<'a> From<&'a Headers> for ::hyper::header::Headers {
+	fn from(headers: &'a Headers) -> ::hyper::header::Headers {
+		let mut converted = ::hyper::header::Headers::new();
+		for header in headers.iter() {
+			converted.set_raw(header.name().to_string(), header.raw().to_vec());
+		}
+		converted
+	}
+}
+
+#[cfg(feature = "http")]
+impl<'a> From<&'a ::http::HeaderMap> for Headers {
+	fn from(headers: &'a ::http::HeaderMap) -> Headers {
+		let mut converted = Headers::new();
+		for (name, value) in headers.iter() {
+			converted.append_raw(name.as_str(), value.as_bytes().to_vec());
+		}
+		converted
+	}
+}
+
+/// Headers whose name or value isn't valid for the `http` crate are left out.
+#[cfg(feature = "http")]
+impl<'a> From<&'a Headers> for ::http::HeaderMap {
+	fn from(headers: &'a Headers) -> ::http::HeaderMap {
+		let mut converted = ::http::HeaderMap::new();
+		for header in headers.iter() {
+			let name = match ::http::header::HeaderName::from_bytes(header.name().as_bytes()) {
+				Ok(name) => name,
+				Err(_) => continue,
+			};
+			for value in header.raw() {
+				if let Ok(value) = ::http::header::HeaderValue::from_bytes(value) {
+					converted.append(name.clone(), value);
+				}
+			}
+		}
+		converted
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn typed_and_raw_access() {
+		let mut headers = Headers::new();
+		headers.set_raw("sec-websocket-protocol", vec![b"chat, superchat".to_vec()]);
+
+		{
+			let protocols = headers.get::<WebSocketProtocol>().unwrap();
+			assert_eq!(protocols.0, vec!["chat".to_string(), "superchat".to_string()]);
+		}
+
+		headers.get_mut::<WebSocketProtocol>().unwrap().0.push("v2".to_string());
+		assert_eq!(headers.get_raw("Sec-WebSocket-Protocol").unwrap(),
+		           &[b"chat, superchat, v2".to_vec()][..]);
+
+		headers.set(Origin("http://example.com".to_string()));
+		assert_eq!(&headers.to_string()[..],
+		           "sec-websocket-protocol: chat, superchat, v2\r\nOrigin: http://example.com\r\n");
+
+		assert!(headers.remove::<Origin>());
+		assert!(!headers.has::<Origin>());
+	}
+
+	#[test]
+	fn repeated_headers() {
+		let mut headers = Headers::new();
+		headers.append_raw("Set-Cookie", b"a=1".to_vec());
+		headers.append_raw("set-cookie", b"b=2".to_vec());
+
+		assert_eq!(headers.len(), 1);
+		assert_eq!(&headers.to_string()[..], "Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n");
+
+		let mut copy = Headers::new();
+		copy.extend(headers.iter());
+		assert_eq!(copy, headers);
+	}
+}
@@ -1,6 +1,6 @@
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::from_one_raw_str;
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::from_one_raw_str;
+use handshake::HttpResult;
 use std::fmt;
 use std::ops::Deref;
 
@@ -20,7 +20,7 @@ impl Header for Origin {
 		"Origin"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<Origin> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<Origin> {
 		from_one_raw_str(raw).map(|s| Origin(s))
 	}
 }
@@ -41,7 +41,7 @@ impl fmt::Display for Origin {
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
-	use hyper::header::Header;
+	use header::Header;
 	use test;
 	#[test]
 	fn test_header_origin() {
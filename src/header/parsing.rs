@@ -0,0 +1,54 @@
+//! Utility functions for Header implementations.
+
+use std::fmt::{self, Display};
+use std::str::{self, FromStr};
+
+use handshake::{HttpError, HttpResult};
+
+/// Reads a single raw string when parsing a header.
+pub fn from_one_raw_str<T>(raw: &[Vec<u8>]) -> HttpResult<T>
+	where T: FromStr
+{
+	if raw.len() != 1 || raw[0].is_empty() {
+		return Err(HttpError::Header);
+	}
+	from_raw_str(&raw[0])
+}
+
+/// Reads a raw string into a value.
+pub fn from_raw_str<T>(raw: &[u8]) -> HttpResult<T>
+	where T: FromStr
+{
+	let s = try!(str::from_utf8(raw)).trim();
+	T::from_str(s).or(Err(HttpError::Header))
+}
+
+/// Reads a comma-delimited raw header into a Vec.
+pub fn from_comma_delimited<T>(raw: &[Vec<u8>]) -> HttpResult<Vec<T>>
+	where T: FromStr
+{
+	let mut result = Vec::new();
+	for s in raw {
+		let s = try!(str::from_utf8(s.as_ref()));
+		result.extend(s.split(',')
+		               .filter_map(|x| match x.trim() {
+			                           "" => None,
+			                           y => Some(y),
+			                          })
+		               .filter_map(|x| x.parse().ok()))
+	}
+	Ok(result)
+}
+
+/// Format an array into a comma-delimited string.
+pub fn fmt_comma_delimited<T>(f: &mut fmt::Formatter, parts: &[T]) -> fmt::Result
+	where T: Display
+{
+	for (i, part) in parts.iter().enumerate() {
+		if i != 0 {
+			try!(f.write_str(", "));
+		}
+		try!(Display::fmt(part, f));
+	}
+	Ok(())
+}
@@ -1,6 +1,6 @@
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::{from_comma_delimited, fmt_comma_delimited};
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::{from_comma_delimited, fmt_comma_delimited};
+use handshake::HttpResult;
 use std::fmt;
 use std::ops::Deref;
 
@@ -22,7 +22,7 @@ impl Header for WebSocketProtocol {
 		"Sec-WebSocket-Protocol"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketProtocol> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<WebSocketProtocol> {
 		from_comma_delimited(raw).map(|vec| WebSocketProtocol(vec))
 	}
 }
@@ -43,7 +43,7 @@ impl fmt::Display for WebSocketProtocol {
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
-	use hyper::header::Header;
+	use header::Header;
 	use test;
 	#[test]
 	fn test_header_protocol() {
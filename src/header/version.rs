@@ -1,6 +1,6 @@
-use hyper::header::{Header, HeaderFormat};
-use hyper::header::parsing::from_one_raw_str;
-use hyper;
+use header::{Header, HeaderFormat};
+use header::parsing::from_one_raw_str;
+use handshake::HttpResult;
 use std::fmt::{self, Debug};
 
 /// Represents a Sec-WebSocket-Version header
@@ -26,7 +26,7 @@ impl Header for WebSocketVersion {
 		"Sec-WebSocket-Version"
 	}
 
-	fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<WebSocketVersion> {
+	fn parse_header(raw: &[Vec<u8>]) -> HttpResult<WebSocketVersion> {
 		from_one_raw_str(raw).map(|s: String| match &s[..] {
 		                              "13" => WebSocketVersion::WebSocket13,
 		                              _ => WebSocketVersion::Unknown(s),
@@ -49,7 +49,7 @@ impl fmt::Display for WebSocketVersion {
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
-	use hyper::header::Header;
+	use header::Header;
 	use test;
 	#[test]
 	fn test_websocket_version() {
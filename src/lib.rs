@@ -36,15 +36,30 @@
 //! # Extending Rust-WebSocket
 //! The `ws` module contains the traits and functions used by Rust-WebSockt at a lower
 //! level. Their usage is explained in the module documentation.
+#[cfg(feature="hyper")]
 extern crate hyper;
+extern crate httparse;
+#[cfg(feature="http")]
+extern crate http;
 extern crate unicase;
 pub extern crate url;
 extern crate rustc_serialize as serialize;
 extern crate rand;
 extern crate byteorder;
 extern crate sha1;
+extern crate socket2;
+#[cfg(unix)]
+extern crate libc;
 #[cfg(feature="ssl")]
 extern crate openssl;
+#[cfg(feature="rustls")]
+extern crate rustls;
+#[cfg(feature="rustls")]
+extern crate webpki_roots;
+#[cfg(feature="rustls")]
+extern crate ring;
+#[cfg(test)]
+extern crate rcgen;
 
 #[macro_use]
 extern crate bitflags;
@@ -81,6 +96,8 @@ macro_rules! upsert_header {
 
 
 pub mod ws;
+pub mod buffer;
+pub mod handshake;
 pub mod client;
 pub mod server;
 pub mod dataframe;
@@ -90,3 +107,4 @@ pub mod stream;
 pub mod header;
 pub mod receiver;
 pub mod sender;
+pub mod reliable;
@@ -2,8 +2,11 @@
 
 use std::io::Read;
 use std::io::Result as IoResult;
+use std::time::Duration;
+use std::sync::Arc;
 
-use hyper::buffer::BufReader;
+use buffer::BufReader;
+use server::request::UpgradeRequest;
 
 use dataframe::{DataFrame, Opcode};
 use result::{WebSocketResult, WebSocketError};
@@ -11,7 +14,7 @@ use ws;
 use ws::dataframe::DataFrame as DataFrameable;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{MessageIterator, DataFrameIterator};
-use stream::{AsTcpStream, Stream};
+use stream::{Transport, Stream};
 pub use stream::Shutdown;
 
 /// This reader bundles an existing stream with a parsing algorithm.
@@ -23,6 +26,8 @@ pub struct Reader<R>
 	pub stream: BufReader<R>,
 	/// the parser to parse bytes into messages
 	pub receiver: Receiver,
+	/// the upgrade request of a server-side client
+	pub request: Option<Arc<UpgradeRequest>>,
 }
 
 impl<R> Reader<R>
@@ -56,21 +61,47 @@ impl<R> Reader<R>
 	{
 		self.receiver.incoming_messages(&mut self.stream)
 	}
+
+	/// The upgrade request a server accepted the client with, `None` for a
+	/// client that connected to a server.
+	pub fn request(&self) -> Option<&UpgradeRequest> {
+		self.request.as_ref().map(|request| &**request)
+	}
 }
 
 impl<S> Reader<S>
-    where S: AsTcpStream + Stream + Read
+    where S: Transport + Stream + Read
 {
 	/// Closes the receiver side of the connection, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
 	pub fn shutdown(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Read)
+		self.stream.get_ref().shutdown(Shutdown::Read)
 	}
 
 	/// Shuts down both Sender and Receiver, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
 	pub fn shutdown_all(&self) -> IoResult<()> {
-		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
+		self.stream.get_ref().shutdown(Shutdown::Both)
+	}
+
+	/// The address of the remote end of the connection.
+	pub fn peer_addr(&self) -> IoResult<S::Addr> {
+		self.stream.get_ref().peer_addr()
+	}
+
+	/// The address of the local end of the connection.
+	pub fn local_addr(&self) -> IoResult<S::Addr> {
+		self.stream.get_ref().local_addr()
+	}
+
+	/// Sets the timeout for reading from the connection, `None` blocks indefinitely.
+	pub fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.get_ref().set_read_timeout(timeout)
+	}
+
+	/// Changes whether the stream is in nonblocking mode.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
+		self.stream.get_ref().set_nonblocking(nonblocking)
 	}
 }
 
@@ -89,6 +120,27 @@ impl Receiver {
 			mask: mask,
 		}
 	}
+
+	/// Create a Receiver that already holds the first frames of a
+	/// fragmented message, the rest of which is still to be read.
+	pub fn with_fragments(mask: bool, fragments: Vec<DataFrame>) -> Receiver {
+		Receiver {
+			buffer: fragments,
+			mask: mask,
+		}
+	}
+
+	/// Whether the data frames received with this receiver have to be
+	/// masked, which is the case for servers but never for clients.
+	pub fn is_masked(&self) -> bool {
+		self.mask
+	}
+
+	/// The frames of a fragmented message received so far, a call to read
+	/// a message was interrupted before its last frame arrived.
+	pub fn fragments(&self) -> &[DataFrame] {
+		&self.buffer
+	}
 }
 
 
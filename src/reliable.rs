@@ -0,0 +1,450 @@
+//! Reliable delivery of messages across connections, on top of `Client`.
+//!
+//! WebSocket only delivers messages as long as the connection lasts. A
+//! `Session` numbers the messages it sends and keeps them until the other
+//! side acknowledges them, so that after a reconnect both sides can send
+//! again what the other didn't get. Both sides of the connection need a
+//! `Session`, the layer has its own framing in binary messages:
+//!
+//! - data: `1`, sequence number, acknowledgement, opcode, payload
+//! - acknowledgement: `2`, acknowledgement
+//! - hello: `3`, acknowledgement, session token
+//!
+//! with numbers as big-endian 64 bit integers, and an acknowledgement
+//! being the sequence number of the last message received in order.
+//!
+//! A client keeps its session and resumes it on every new connection:
+//!
+//! ```rust,no_run
+//! # use websocket::ClientBuilder;
+//! use websocket::Message;
+//! use websocket::reliable::Session;
+//!
+//! let mut session = Session::new(1024);
+//! loop {
+//!     let mut client = ClientBuilder::new("ws://127.0.0.1:2794").unwrap()
+//!         .connect_insecure()
+//!         .unwrap();
+//!     session.resume(&mut client).unwrap();
+//!     session.send(&mut client, &Message::text("order #1")).unwrap();
+//!     while let Ok(message) = session.recv(&mut client) {
+//!         // every message once, in order
+//!     }
+//! }
+//! ```
+//!
+//! A server looks the session up by the token of the hello:
+//!
+//! ```rust,no_run
+//! use std::collections::HashMap;
+//! use websocket::Server;
+//! use websocket::reliable::{Session, Hello};
+//!
+//! let mut sessions = HashMap::new();
+//! let mut server = Server::bind("127.0.0.1:2794").unwrap();
+//! let mut client = server.accept().ok().unwrap().accept().unwrap();
+//!
+//! let hello = Hello::read(&mut client).unwrap();
+//! let session = sessions.entry(hello.token.clone())
+//!                       .or_insert_with(|| Session::with_token(hello.token.clone(), 1024));
+//! session.accept(&mut client, &hello).unwrap();
+//! let message = session.recv(&mut client).unwrap();
+//! ```
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Cursor, Read};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serialize::hex::ToHex;
+use rand;
+
+use client::Client;
+use message::{Message, Type};
+use result::{WebSocketResult, WebSocketError};
+use stream::Stream;
+
+const DATA: u8 = 1;
+const ACK: u8 = 2;
+const HELLO: u8 = 3;
+
+/// How many messages are received before they are acknowledged on their
+/// own, when there is nothing to send to carry the acknowledgement.
+pub const DEFAULT_ACK_INTERVAL: u64 = 16;
+
+/// What one side of a reliable session says first on every connection:
+/// which session it is and how far it got.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+	/// The token of the session.
+	pub token: String,
+	/// The sequence number of the last message received in order.
+	pub received: u64,
+}
+
+impl Hello {
+	/// Reads the hello of the other side, which has to be the first
+	/// message on the connection.
+	pub fn read<S>(client: &mut Client<S>) -> WebSocketResult<Hello>
+		where S: Stream
+	{
+		let message: Message = try!(client.recv_message());
+		match try!(Frame::parse(&message)) {
+			Frame::Hello(hello) => Ok(hello),
+			_ => Err(WebSocketError::DeliveryError("Expected a hello")),
+		}
+	}
+
+	fn write<S>(&self, client: &mut Client<S>) -> WebSocketResult<()>
+		where S: Stream
+	{
+		let mut payload = vec![HELLO];
+		try!(payload.write_u64::<BigEndian>(self.received));
+		payload.extend(self.token.as_bytes());
+		client.send_message(&Message::binary(payload))
+	}
+}
+
+/// The state of one side of a reliable session, which outlives the
+/// connections it is used over.
+#[derive(Clone, Debug)]
+pub struct Session {
+	token: String,
+	/// The sequence number of the next message sent.
+	next: u64,
+	/// Sent messages that weren't acknowledged yet, in order.
+	unacked: VecDeque<(u64, Message<'static>)>,
+	capacity: usize,
+	/// The sequence number of the last message received in order.
+	received: u64,
+	/// Messages received ahead of one that is missing.
+	early: BTreeMap<u64, Message<'static>>,
+	/// Messages received since the last acknowledgement was sent.
+	unacknowledged: u64,
+	ack_interval: u64,
+}
+
+impl Session {
+	/// A new session with a random token, keeping at most `capacity` sent
+	/// messages until they are acknowledged.
+	pub fn new(capacity: usize) -> Self {
+		Session::with_token(rand::random::<[u8; 16]>().to_hex(), capacity)
+	}
+
+	/// A new session with the given token, which a server uses for the
+	/// token a new client says hello with.
+	pub fn with_token(token: String, capacity: usize) -> Self {
+		Session {
+			token: token,
+			next: 1,
+			unacked: VecDeque::new(),
+			capacity: capacity,
+			received: 0,
+			early: BTreeMap::new(),
+			unacknowledged: 0,
+			ack_interval: DEFAULT_ACK_INTERVAL,
+		}
+	}
+
+	/// Acknowledges received messages on their own after `interval` of
+	/// them, `DEFAULT_ACK_INTERVAL` by default. Sending a message always
+	/// acknowledges everything received so far.
+	pub fn ack_interval(mut self, interval: u64) -> Self {
+		self.ack_interval = interval.max(1);
+		self
+	}
+
+	/// The token the session is known by on both sides.
+	pub fn token(&self) -> &str {
+		&self.token
+	}
+
+	/// How many sent messages weren't acknowledged yet.
+	pub fn unacked(&self) -> usize {
+		self.unacked.len()
+	}
+
+	/// The sequence number of the last message received in order.
+	pub fn received(&self) -> u64 {
+		self.received
+	}
+
+	/// Starts using the session on a new connection, on the side that
+	/// connected: says hello, reads the hello of the other side and sends
+	/// again what it is missing.
+	pub fn resume<S>(&mut self, client: &mut Client<S>) -> WebSocketResult<()>
+		where S: Stream
+	{
+		try!(self.hello().write(client));
+		let hello = try!(Hello::read(client));
+		if hello.token != self.token {
+			return Err(WebSocketError::DeliveryError("Session token doesn't match"));
+		}
+		self.replay(client, hello.received)
+	}
+
+	/// Starts using the session on a new connection, on the side that
+	/// accepted it once the hello of the other side was read: says hello
+	/// back and sends again what the other side is missing.
+	pub fn accept<S>(&mut self, client: &mut Client<S>, hello: &Hello) -> WebSocketResult<()>
+		where S: Stream
+	{
+		if hello.token != self.token {
+			return Err(WebSocketError::DeliveryError("Session token doesn't match"));
+		}
+		try!(self.hello().write(client));
+		self.replay(client, hello.received)
+	}
+
+	fn hello(&mut self) -> Hello {
+		self.unacknowledged = 0;
+		Hello {
+			token: self.token.clone(),
+			received: self.received,
+		}
+	}
+
+	fn replay<S>(&mut self, client: &mut Client<S>, received: u64) -> WebSocketResult<()>
+		where S: Stream
+	{
+		try!(self.acknowledged(received));
+		for &(seq, ref message) in &self.unacked {
+			try!(client.send_message(&data(seq, self.received, message)));
+		}
+		Ok(())
+	}
+
+	/// Sends a text or binary message, which is kept until the other side
+	/// acknowledges it. Returns its sequence number. Fails without sending
+	/// if too many messages are waiting for acknowledgement already; the
+	/// message is kept even if sending fails otherwise.
+	pub fn send<S>(&mut self, client: &mut Client<S>, message: &Message) -> WebSocketResult<u64>
+		where S: Stream
+	{
+		match message.opcode {
+			Type::Text | Type::Binary => {}
+			_ => return Err(WebSocketError::DeliveryError("Only text and binary messages can be sent reliably")),
+		}
+		if self.unacked.len() >= self.capacity {
+			return Err(WebSocketError::DeliveryError("Too many messages wait for acknowledgement"));
+		}
+		let seq = self.next;
+		self.next += 1;
+		let message = Message {
+			opcode: message.opcode,
+			cd_status_code: None,
+			payload: Cow::Owned(message.payload.to_vec()),
+		};
+		let frame = data(seq, self.received, &message);
+		self.unacked.push_back((seq, message));
+		self.unacknowledged = 0;
+		try!(client.send_message(&frame));
+		Ok(seq)
+	}
+
+	/// Receives the next message the other side sent, every one exactly
+	/// once and in order. Acknowledgements are dealt with on the way, and
+	/// messages other than the ones of the session, like pings and closes,
+	/// are returned as they are.
+	pub fn recv<S>(&mut self, client: &mut Client<S>) -> WebSocketResult<Message<'static>>
+		where S: Stream
+	{
+		loop {
+			if let Some(message) = self.early.remove(&(self.received + 1)) {
+				return self.deliver(client, message);
+			}
+			let message: Message = try!(client.recv_message());
+			if message.opcode != Type::Binary {
+				return Ok(message);
+			}
+			match try!(Frame::parse(&message)) {
+				Frame::Data(seq, ack, message) => {
+					try!(self.acknowledged(ack));
+					if seq == self.received + 1 {
+						return self.deliver(client, message);
+					}
+					if seq > self.received + 1 && self.early.len() < self.capacity {
+						self.early.insert(seq, message);
+					}
+					// anything else was delivered already
+				}
+				Frame::Ack(ack) => try!(self.acknowledged(ack)),
+				Frame::Hello(_) => return Err(WebSocketError::DeliveryError("Unexpected hello")),
+			}
+		}
+	}
+
+	fn deliver<S>(&mut self, client: &mut Client<S>, message: Message<'static>) -> WebSocketResult<Message<'static>>
+		where S: Stream
+	{
+		self.received += 1;
+		self.unacknowledged += 1;
+		if self.unacknowledged >= self.ack_interval {
+			try!(self.ack(client));
+		}
+		Ok(message)
+	}
+
+	/// Acknowledges everything received so far right away.
+	pub fn ack<S>(&mut self, client: &mut Client<S>) -> WebSocketResult<()>
+		where S: Stream
+	{
+		self.unacknowledged = 0;
+		let mut payload = vec![ACK];
+		try!(payload.write_u64::<BigEndian>(self.received));
+		client.send_message(&Message::binary(payload))
+	}
+
+	/// Forgets the sent messages up to `ack`.
+	fn acknowledged(&mut self, ack: u64) -> WebSocketResult<()> {
+		if ack >= self.next {
+			return Err(WebSocketError::DeliveryError("Acknowledged a message that wasn't sent"));
+		}
+		while self.unacked.front().map_or(false, |&(seq, _)| seq <= ack) {
+			self.unacked.pop_front();
+		}
+		Ok(())
+	}
+}
+
+fn data(seq: u64, ack: u64, message: &Message) -> Message<'static> {
+	let mut payload = Vec::with_capacity(18 + message.payload.len());
+	payload.push(DATA);
+	payload.write_u64::<BigEndian>(seq).unwrap();
+	payload.write_u64::<BigEndian>(ack).unwrap();
+	payload.push(message.opcode as u8);
+	payload.extend(message.payload.iter());
+	Message::binary(payload)
+}
+
+enum Frame {
+	Data(u64, u64, Message<'static>),
+	Ack(u64),
+	Hello(Hello),
+}
+
+impl Frame {
+	fn parse(message: &Message) -> WebSocketResult<Frame> {
+		let invalid = WebSocketError::DeliveryError("Not a reliable delivery frame");
+		if message.opcode != Type::Binary || message.payload.is_empty() {
+			return Err(invalid);
+		}
+		let mut reader = Cursor::new(&message.payload[1..]);
+		let frame = match message.payload[0] {
+			DATA => {
+				let seq = try!(reader.read_u64::<BigEndian>());
+				let ack = try!(reader.read_u64::<BigEndian>());
+				let opcode = match try!(reader.read_u8()) {
+					1 => Type::Text,
+					2 => Type::Binary,
+					_ => return Err(invalid),
+				};
+				let mut payload = Vec::new();
+				try!(reader.read_to_end(&mut payload));
+				Frame::Data(seq,
+				            ack,
+				            Message {
+				                opcode: opcode,
+				                cd_status_code: None,
+				                payload: Cow::Owned(payload),
+				            })
+			}
+			ACK => Frame::Ack(try!(reader.read_u64::<BigEndian>())),
+			HELLO => {
+				let received = try!(reader.read_u64::<BigEndian>());
+				let mut token = String::new();
+				try!(reader.read_to_string(&mut token));
+				Frame::Hello(Hello {
+					             token: token,
+					             received: received,
+					            })
+			}
+			_ => return Err(invalid),
+		};
+		Ok(frame)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use std::sync::mpsc;
+	use client::ClientBuilder;
+	use server::Server;
+
+	#[test]
+	fn replays_after_reconnect() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		let (sent, all_sent) = mpsc::channel();
+
+		let remote = thread::spawn(move || {
+			let mut session = None;
+			for round in 0..2 {
+				let mut client = server.accept().ok().unwrap().accept().unwrap();
+				let hello = Hello::read(&mut client).unwrap();
+				let session = session.get_or_insert_with(|| Session::with_token(hello.token.clone(), 8));
+				session.accept(&mut client, &hello).unwrap();
+				if round == 0 {
+					session.send(&mut client, &Message::text("lost on the way")).unwrap();
+					assert_eq!(session.recv(&mut client).unwrap(), Message::text("one"));
+					assert_eq!(session.recv(&mut client).unwrap(), Message::text("two"));
+					// the connection drops before "three" is read
+					all_sent.recv().unwrap();
+				} else {
+					assert_eq!(session.recv(&mut client).unwrap(), Message::text("three"));
+					assert_eq!(session.recv(&mut client).unwrap(), Message::text("four"));
+					assert_eq!(session.unacked(), 0);
+				}
+			}
+		});
+
+		let mut session = Session::new(8).ack_interval(100);
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		session.resume(&mut client).unwrap();
+		for text in &["one", "two", "three"] {
+			session.send(&mut client, &Message::text(*text)).unwrap();
+		}
+		assert_eq!(session.unacked(), 3);
+		sent.send(()).unwrap();
+
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		session.resume(&mut client).unwrap();
+		// only "three" is sent again, the hello acknowledged the others
+		assert_eq!(session.unacked(), 1);
+		assert_eq!(session.recv(&mut client).unwrap(), Message::text("lost on the way"));
+		session.send(&mut client, &Message::text("four")).unwrap();
+		remote.join().unwrap();
+	}
+
+	#[test]
+	fn drops_duplicates_and_reorders() {
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", server.local_addr().unwrap());
+
+		let remote = thread::spawn(move || {
+			let mut client = server.accept().ok().unwrap().accept().unwrap();
+			let hello = Hello::read(&mut client).unwrap();
+			let mut session = Session::with_token(hello.token.clone(), 8).ack_interval(1);
+			session.accept(&mut client, &hello).unwrap();
+			let texts: Vec<Message> = (0..3).map(|_| session.recv(&mut client).unwrap()).collect();
+			assert_eq!(texts, [Message::text("1"), Message::text("2"), Message::text("3")]);
+			session.send(&mut client, &Message::text("done")).unwrap();
+		});
+
+		let mut session = Session::new(8);
+		let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+		session.resume(&mut client).unwrap();
+		// what a session would send, but out of order and twice
+		for &seq in &[2, 1, 2, 1, 3] {
+			client.send_message(&data(seq, 0, &Message::text(seq.to_string()))).unwrap();
+		}
+		for seq in 1..4 {
+			session.unacked.push_back((seq, Message::text(seq.to_string())));
+		}
+		session.next = 4;
+
+		assert_eq!(session.recv(&mut client).unwrap(), Message::text("done"));
+		assert_eq!(session.unacked(), 0);
+		remote.join().unwrap();
+	}
+}
@@ -5,13 +5,18 @@ use std::str::Utf8Error;
 use std::error::Error;
 use std::convert::From;
 use std::fmt;
-use hyper::Error as HttpError;
+use std::net::SocketAddr;
+use std::time::Duration;
+use header::Headers;
+use handshake::HttpError;
 use url::ParseError;
 
 #[cfg(feature="ssl")]
 use openssl::error::ErrorStack as SslError;
 #[cfg(feature="ssl")]
 use openssl::ssl::HandshakeError as SslHandshakeError;
+#[cfg(feature="rustls")]
+use rustls::Error as RustlsError;
 
 /// The type used for WebSocket results
 pub type WebSocketResult<T> = Result<T, WebSocketError>;
@@ -25,6 +30,11 @@ pub enum WebSocketError {
 	RequestError(&'static str),
 	/// Invalid WebSocket response error
 	ResponseError(&'static str),
+	/// The server answered the upgrade request, but didn't accept it
+	UpgradeRefused(Box<UpgradeResponse>),
+	/// The server accepted the upgrade with a subprotocol or extensions the
+	/// client didn't ask for
+	NegotiationError(NegotiationErrorKind),
 	/// Invalid WebSocket data frame error
 	DataFrameError(&'static str),
 	/// No data available
@@ -35,6 +45,10 @@ pub enum WebSocketError {
 	HttpError(HttpError),
 	/// A URL parsing error
 	UrlError(ParseError),
+	/// None of the addresses of the server could be connected to
+	ConnectError(ConnectError),
+	/// The proxy couldn't be used or refused to open a tunnel
+	ProxyError(&'static str),
 	/// A WebSocket URL error
 	WebSocketUrlError(WSUrlErrorKind),
 	/// An SSL error
@@ -46,6 +60,16 @@ pub enum WebSocketError {
 	/// an ssl handshake interruption
 	#[cfg(feature="ssl")]
 	SslHandshakeInterruption,
+	/// A TLS error from rustls
+	#[cfg(feature="rustls")]
+	RustlsError(RustlsError),
+	/// The TLS settings couldn't be used or the server's certificate
+	/// didn't match them
+	TlsError(&'static str),
+	/// A client passed from another process couldn't be taken over
+	HandoffError(&'static str),
+	/// A reliable delivery session can't go on
+	DeliveryError(&'static str),
 	/// A UTF-8 error
 	Utf8Error(Utf8Error),
 }
@@ -64,17 +88,26 @@ impl Error for WebSocketError {
 			WebSocketError::ProtocolError(_) => "WebSocket protocol error",
 			WebSocketError::RequestError(_) => "WebSocket request error",
 			WebSocketError::ResponseError(_) => "WebSocket response error",
+			WebSocketError::UpgradeRefused(_) => "WebSocket upgrade refused",
+			WebSocketError::NegotiationError(_) => "WebSocket negotiation failure",
 			WebSocketError::DataFrameError(_) => "WebSocket data frame error",
 			WebSocketError::NoDataAvailable => "No data available",
 			WebSocketError::IoError(_) => "I/O failure",
 			WebSocketError::HttpError(_) => "HTTP failure",
 			WebSocketError::UrlError(_) => "URL failure",
+			WebSocketError::ConnectError(_) => "Connection failure",
+			WebSocketError::ProxyError(_) => "Proxy failure",
 			#[cfg(feature="ssl")]
 			      WebSocketError::SslError(_) => "SSL failure",
 			#[cfg(feature="ssl")]
             WebSocketError::SslHandshakeFailure => "SSL Handshake failure",
 			#[cfg(feature="ssl")]
             WebSocketError::SslHandshakeInterruption => "SSL Handshake interrupted",
+			#[cfg(feature="rustls")]
+			WebSocketError::RustlsError(_) => "TLS failure",
+			WebSocketError::TlsError(_) => "TLS configuration or verification failure",
+			WebSocketError::HandoffError(_) => "Client handoff failure",
+			WebSocketError::DeliveryError(_) => "Reliable delivery failure",
 			WebSocketError::Utf8Error(_) => "UTF-8 failure",
 			WebSocketError::WebSocketUrlError(_) => "WebSocket URL failure",
 		}
@@ -85,8 +118,13 @@ impl Error for WebSocketError {
 			WebSocketError::IoError(ref error) => Some(error),
 			WebSocketError::HttpError(ref error) => Some(error),
 			WebSocketError::UrlError(ref error) => Some(error),
+			WebSocketError::ConnectError(ref error) => Some(error),
+			WebSocketError::UpgradeRefused(ref error) => Some(&**error),
+			WebSocketError::NegotiationError(ref error) => Some(error),
 			#[cfg(feature="ssl")]
 			      WebSocketError::SslError(ref error) => Some(error),
+			#[cfg(feature="rustls")]
+			WebSocketError::RustlsError(ref error) => Some(error),
 			WebSocketError::Utf8Error(ref error) => Some(error),
 			WebSocketError::WebSocketUrlError(ref error) => Some(error),
 			_ => None,
@@ -133,6 +171,19 @@ impl<T> From<SslHandshakeError<T>> for WebSocketError {
 	}
 }
 
+#[cfg(feature="rustls")]
+impl From<RustlsError> for WebSocketError {
+	fn from(err: RustlsError) -> WebSocketError {
+		WebSocketError::RustlsError(err)
+	}
+}
+
+impl From<ConnectError> for WebSocketError {
+	fn from(err: ConnectError) -> WebSocketError {
+		WebSocketError::ConnectError(err)
+	}
+}
+
 impl From<Utf8Error> for WebSocketError {
 	fn from(err: Utf8Error) -> WebSocketError {
 		WebSocketError::Utf8Error(err)
@@ -154,6 +205,8 @@ pub enum WSUrlErrorKind {
 	InvalidScheme,
 	/// There is no hostname or IP address to connect to
 	NoHostName,
+	/// A `ws+unix` URL has no path to a socket to connect to
+	NoSocketPath,
 }
 
 impl fmt::Display for WSUrlErrorKind {
@@ -170,6 +223,140 @@ impl Error for WSUrlErrorKind {
 			WSUrlErrorKind::CannotSetFragment => "WebSocket URL cannot set fragment",
 			WSUrlErrorKind::InvalidScheme => "WebSocket URL invalid scheme",
 			WSUrlErrorKind::NoHostName => "WebSocket URL no host name provided",
+			WSUrlErrorKind::NoSocketPath => "WebSocket URL no socket path provided",
+		}
+	}
+}
+
+/// How the subprotocol or extensions the server chose don't fit the upgrade
+/// request, which RFC 6455 section 4.1 says the client must fail the
+/// connection for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NegotiationErrorKind {
+	/// The server chose a subprotocol the client didn't offer
+	UnofferedProtocol(String),
+	/// The server chose more than one subprotocol
+	SeveralProtocols,
+	/// The server accepted an extension the client didn't offer
+	UnofferedExtension(String),
+	/// The server accepted the same extension more than once
+	RepeatedExtension(String),
+	/// The server accepted an extension with a parameter that wasn't offered
+	/// and the extension doesn't let it add
+	InvalidExtensionParameter(String, String),
+}
+
+impl fmt::Display for NegotiationErrorKind {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		try!(fmt.write_str(self.description()));
+		match *self {
+			NegotiationErrorKind::UnofferedProtocol(ref name) |
+			NegotiationErrorKind::UnofferedExtension(ref name) |
+			NegotiationErrorKind::RepeatedExtension(ref name) => write!(fmt, ": {}", name),
+			NegotiationErrorKind::InvalidExtensionParameter(ref name, ref param) => {
+				write!(fmt, ": {}; {}", name, param)
+			}
+			NegotiationErrorKind::SeveralProtocols => Ok(()),
+		}
+	}
+}
+
+impl Error for NegotiationErrorKind {
+	fn description(&self) -> &str {
+		match *self {
+			NegotiationErrorKind::UnofferedProtocol(_) => "Server chose a subprotocol that wasn't offered",
+			NegotiationErrorKind::SeveralProtocols => "Server chose more than one subprotocol",
+			NegotiationErrorKind::UnofferedExtension(_) => "Server accepted an extension that wasn't offered",
+			NegotiationErrorKind::RepeatedExtension(_) => "Server accepted an extension more than once",
+			NegotiationErrorKind::InvalidExtensionParameter(..) => {
+				"Server accepted an extension with a parameter it can't have"
+			}
+		}
+	}
+}
+
+/// The addresses a client tried to connect to, and why each attempt failed.
+#[derive(Debug)]
+pub struct ConnectError {
+	/// Every address that was tried, in the order the attempts failed.
+	pub attempts: Vec<(SocketAddr, io::Error)>,
+}
+
+impl ConnectError {
+	/// Whether the connection failed because it took too long.
+	pub fn is_timeout(&self) -> bool {
+		self.attempts
+		    .iter()
+		    .any(|&(_, ref error)| error.kind() == io::ErrorKind::TimedOut)
+	}
+}
+
+impl fmt::Display for ConnectError {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		if self.attempts.is_empty() {
+			return fmt.write_str("No address to connect to");
+		}
+		try!(fmt.write_str("Could not connect to"));
+		for (i, &(ref addr, ref error)) in self.attempts.iter().enumerate() {
+			try!(write!(fmt, "{} {} ({})", if i == 0 { "" } else { "," }, addr, error));
 		}
+		Ok(())
+	}
+}
+
+impl Error for ConnectError {
+	fn description(&self) -> &str {
+		"Could not connect to any address"
+	}
+}
+
+/// The response of a server that didn't accept the upgrade, either with
+/// another status than `101 Switching Protocols` or with headers that
+/// don't complete the handshake.
+#[derive(Debug)]
+pub struct UpgradeResponse {
+	/// The status code, like `401`.
+	pub status: u16,
+	/// The reason phrase of the status, like `Unauthorized`.
+	pub reason: String,
+	/// The headers of the response.
+	pub headers: Headers,
+	/// As much of the body as was read, at most
+	/// `client::MAX_REFUSAL_BODY_SIZE` bytes.
+	pub body: Vec<u8>,
+	/// Whether the body was longer than what was read.
+	pub truncated: bool,
+	/// What was wrong with the response.
+	pub problem: &'static str,
+}
+
+impl UpgradeResponse {
+	/// How long the server asks to wait before trying again, from a
+	/// `Retry-After` header given in seconds, as is usual with `429` and
+	/// `503` responses.
+	pub fn retry_after(&self) -> Option<Duration> {
+		let value = match self.headers.get_raw("Retry-After") {
+			Some(value) if value.len() == 1 => String::from_utf8_lossy(&value[0]).into_owned(),
+			_ => return None,
+		};
+		value.trim().parse().ok().map(Duration::from_secs)
+	}
+}
+
+impl fmt::Display for UpgradeResponse {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		try!(write!(fmt, "{} {}", self.status, self.reason));
+		let body = String::from_utf8_lossy(&self.body);
+		let body = body.trim();
+		if !body.is_empty() {
+			try!(write!(fmt, ": {}", body));
+		}
+		Ok(())
+	}
+}
+
+impl Error for UpgradeResponse {
+	fn description(&self) -> &str {
+		self.problem
 	}
 }
@@ -2,9 +2,12 @@
 
 use std::io::Write;
 use std::io::Result as IoResult;
+use std::time::Duration;
+use std::sync::Arc;
 use result::WebSocketResult;
 use ws::dataframe::DataFrame;
-use stream::AsTcpStream;
+use stream::{Transport, SharedStream};
+use server::request::UpgradeRequest;
 use ws;
 use ws::sender::Sender as SenderTrait;
 pub use stream::Shutdown;
@@ -18,6 +21,8 @@ pub struct Writer<W> {
 	pub stream: W,
 	/// The serializer that will be used to serialize the messages
 	pub sender: Sender,
+	/// The upgrade request of a server-side client
+	pub request: Option<Arc<UpgradeRequest>>,
 }
 
 impl<W> Writer<W>
@@ -38,26 +43,136 @@ impl<W> Writer<W>
 	{
 		self.sender.send_message(&mut self.stream, message)
 	}
+
+	/// The upgrade request a server accepted the client with, `None` for a
+	/// client that connected to a server.
+	pub fn request(&self) -> Option<&UpgradeRequest> {
+		self.request.as_ref().map(|request| &**request)
+	}
 }
 
 impl<S> Writer<S>
-    where S: AsTcpStream + Write
+    where S: Transport + Write
 {
 	/// Closes the sender side of the connection, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
 	pub fn shutdown(&self) -> IoResult<()> {
-		self.stream.as_tcp().shutdown(Shutdown::Write)
+		self.stream.shutdown(Shutdown::Write)
 	}
 
 	/// Shuts down both Sender and Receiver, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
 	pub fn shutdown_all(&self) -> IoResult<()> {
-		self.stream.as_tcp().shutdown(Shutdown::Both)
+		self.stream.shutdown(Shutdown::Both)
+	}
+
+	/// The address of the remote end of the connection.
+	pub fn peer_addr(&self) -> IoResult<S::Addr> {
+		self.stream.peer_addr()
+	}
+
+	/// The address of the local end of the connection.
+	pub fn local_addr(&self) -> IoResult<S::Addr> {
+		self.stream.local_addr()
+	}
+
+	/// Sets the `TCP_NODELAY` option, disabling Nagle's algorithm.
+	pub fn set_nodelay(&self, nodelay: bool) -> IoResult<()> {
+		self.stream.set_nodelay(nodelay)
+	}
+
+	/// Sets the timeout for writing to the connection, `None` blocks indefinitely.
+	pub fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+		self.stream.set_write_timeout(timeout)
+	}
+
+	/// Changes whether the stream is in nonblocking mode.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
+		self.stream.set_nonblocking(nonblocking)
+	}
+}
+
+/// A writer for a `SharedStream`, this is the writing component given by
+/// the client's `.split_shared()` function.
+///
+/// It can be cloned, and every clone can send from its own thread. Each
+/// message is serialized before it is written so messages sent from
+/// different threads never get mixed up on the wire.
+pub struct SharedWriter<S> {
+	/// The stream that websocket messages will be written to
+	pub stream: SharedStream<S>,
+	/// The serializer that will be used to serialize the messages
+	pub sender: Sender,
+	/// The upgrade request of a server-side client
+	pub request: Option<Arc<UpgradeRequest>>,
+}
+
+impl<S> SharedWriter<S>
+    where S: Transport + Write
+{
+	/// Sends a single data frame to the remote endpoint.
+	pub fn send_dataframe<D>(&mut self, dataframe: &D) -> WebSocketResult<()>
+		where D: DataFrame
+	{
+		let mut buf = Vec::with_capacity(dataframe.size() + 14);
+		try!(self.sender.send_dataframe(&mut buf, dataframe));
+		try!(self.stream.write_all_exclusive(&buf));
+		Ok(())
+	}
+
+	/// Sends a single message to the remote endpoint.
+	pub fn send_message<'m, M, D>(&mut self, message: &'m M) -> WebSocketResult<()>
+		where M: ws::Message<'m, D>,
+		      D: DataFrame
+	{
+		let mut buf = Vec::new();
+		try!(self.sender.send_message(&mut buf, message));
+		try!(self.stream.write_all_exclusive(&buf));
+		Ok(())
+	}
+
+	/// Closes the sender side of the connection, will cause all pending and future IO to
+	/// return immediately with an appropriate value.
+	pub fn shutdown(&self) -> IoResult<()> {
+		self.stream.shutdown(Shutdown::Write)
+	}
+
+	/// Shuts down both Sender and Receiver, will cause all pending and future IO to
+	/// return immediately with an appropriate value.
+	pub fn shutdown_all(&self) -> IoResult<()> {
+		self.stream.shutdown(Shutdown::Both)
+	}
+
+	/// The address of the remote end of the connection.
+	pub fn peer_addr(&self) -> IoResult<S::Addr> {
+		self.stream.peer_addr()
+	}
+
+	/// The address of the local end of the connection.
+	pub fn local_addr(&self) -> IoResult<S::Addr> {
+		self.stream.local_addr()
+	}
+
+	/// The upgrade request a server accepted the client with, `None` for a
+	/// client that connected to a server.
+	pub fn request(&self) -> Option<&UpgradeRequest> {
+		self.request.as_ref().map(|request| &**request)
+	}
+}
+
+impl<S> Clone for SharedWriter<S> {
+	fn clone(&self) -> SharedWriter<S> {
+		SharedWriter {
+			stream: self.stream.clone(),
+			sender: self.sender.clone(),
+			request: self.request.clone(),
+		}
 	}
 }
 
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
+#[derive(Clone)]
 pub struct Sender {
 	mask: bool,
 }
@@ -67,6 +182,12 @@ impl Sender {
 	pub fn new(mask: bool) -> Sender {
 		Sender { mask: mask }
 	}
+
+	/// Whether the data frames sent with this sender are masked,
+	/// which is the case for clients but never for servers.
+	pub fn is_masked(&self) -> bool {
+		self.mask
+	}
 }
 
 impl ws::Sender for Sender {
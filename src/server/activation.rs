@@ -0,0 +1,191 @@
+//! Listeners passed in by systemd socket activation.
+//! This module is only available on Unix.
+//!
+//! With a `.socket` unit systemd binds the sockets itself and starts the
+//! service with them as file descriptors 3 and up, telling it how many in
+//! `LISTEN_FDS` and for which process in `LISTEN_PID`. This is the protocol
+//! of `sd_listen_fds(3)`, and other supervisors use it as well.
+//!
+//! ```rust,no_run
+//! use std::thread;
+//! use websocket::Server;
+//! use websocket::server::activation::{self, Listener};
+//!
+//! for listener in activation::listen_fds().unwrap() {
+//!     thread::spawn(move || match listener {
+//!         Listener::Tcp(listener) => {
+//!             for connection in Server::from_listener(listener).filter_map(Result::ok) {
+//!                 // ...
+//!             }
+//!         }
+//!         Listener::Unix(listener) => {
+//!             for connection in Server::from_unix_listener(listener).filter_map(Result::ok) {
+//!                 // ...
+//!             }
+//!         }
+//!     });
+//! }
+//! ```
+use std::env;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::io::{RawFd, FromRawFd};
+use std::os::unix::net::UnixListener;
+use std::process;
+use libc;
+
+/// The first file descriptor passed by systemd.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// A listening socket that was passed to this process.
+#[derive(Debug)]
+pub enum Listener {
+	/// A TCP socket, IPv4 or IPv6
+	Tcp(TcpListener),
+	/// A Unix domain socket
+	Unix(UnixListener),
+}
+
+/// Takes the listeners passed through `LISTEN_FDS` and `LISTEN_PID`, in
+/// the order they were passed. There are none if the variables aren't set
+/// or are meant for another process.
+///
+/// The variables are removed from the environment so child processes
+/// don't think the sockets are meant for them, which also means the
+/// listeners can only be taken once.
+pub fn listen_fds() -> io::Result<Vec<Listener>> {
+	let pid = env::var("LISTEN_PID").ok();
+	let fds = env::var("LISTEN_FDS").ok();
+	env::remove_var("LISTEN_PID");
+	env::remove_var("LISTEN_FDS");
+	env::remove_var("LISTEN_FDNAMES");
+
+	let count = try!(passed_count(pid.as_ref().map(|pid| &pid[..]),
+	                              fds.as_ref().map(|fds| &fds[..]),
+	                              process::id()));
+	// the environment says these are ours
+	unsafe { listeners_at(LISTEN_FDS_START, count) }
+}
+
+/// How many sockets `LISTEN_PID` and `LISTEN_FDS` say were passed to the
+/// process `own`, none if either isn't set or they are for another one.
+fn passed_count(pid: Option<&str>, fds: Option<&str>, own: u32) -> io::Result<RawFd> {
+	let (pid, fds) = match (pid, fds) {
+		(Some(pid), Some(fds)) => (pid, fds),
+		_ => return Ok(0),
+	};
+	let pid: u32 = try!(pid.trim().parse().map_err(|_| invalid("LISTEN_PID is not a process id")));
+	if pid != own {
+		return Ok(0);
+	}
+	fds.trim()
+	   .parse()
+	   .ok()
+	   .and_then(|count: RawFd| if count >= 0 { Some(count) } else { None })
+	   .ok_or_else(|| invalid("LISTEN_FDS is not a number"))
+}
+
+/// Takes ownership of the `count` listening sockets starting at file
+/// descriptor `start`, for listeners inherited from a parent process in
+/// some other way.
+///
+/// This is unsafe since nothing else may own or close those file
+/// descriptors afterwards.
+pub unsafe fn listeners_at(start: RawFd, count: RawFd) -> io::Result<Vec<Listener>> {
+	let mut listeners = Vec::new();
+	for fd in start..start + count {
+		listeners.push(try!(listener(fd)));
+	}
+	Ok(listeners)
+}
+
+unsafe fn listener(fd: RawFd) -> io::Result<Listener> {
+	// the sockets shouldn't leak into processes this one starts
+	if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	// datagram sockets and sockets that aren't listening can be passed too
+	if try!(socket_option(fd, libc::SO_TYPE)) != libc::SOCK_STREAM {
+		return Err(invalid("passed file descriptor is not a stream socket"));
+	}
+	if try!(socket_option(fd, libc::SO_ACCEPTCONN)) == 0 {
+		return Err(invalid("passed socket is not listening"));
+	}
+
+	let mut addr: libc::sockaddr_storage = mem::zeroed();
+	let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+	if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) < 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	match addr.ss_family as libc::c_int {
+		libc::AF_INET | libc::AF_INET6 => Ok(Listener::Tcp(TcpListener::from_raw_fd(fd))),
+		libc::AF_UNIX => Ok(Listener::Unix(UnixListener::from_raw_fd(fd))),
+		_ => Err(invalid("passed file descriptor is not a TCP or Unix socket")),
+	}
+}
+
+unsafe fn socket_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+	let mut value: libc::c_int = 0;
+	let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+	if libc::getsockopt(fd, libc::SOL_SOCKET, option, &mut value as *mut _ as *mut libc::c_void, &mut len) < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(value)
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::fs;
+	use std::net::{TcpStream, UdpSocket};
+	use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+	#[test]
+	fn takes_passed_listeners() {
+		let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = tcp.local_addr().unwrap();
+		let path = env::temp_dir().join(format!("websocket-activation-{}.sock", process::id()));
+		let _ = fs::remove_file(&path);
+		let unix = UnixListener::bind(&path).unwrap();
+
+		let listeners = unsafe { listeners_at(tcp.into_raw_fd(), 1).unwrap() };
+		match listeners[0] {
+			Listener::Tcp(ref listener) => assert_eq!(listener.local_addr().unwrap(), addr),
+			_ => panic!("expected a TCP listener"),
+		}
+		let listeners = unsafe { listeners_at(unix.into_raw_fd(), 1).unwrap() };
+		match listeners[0] {
+			Listener::Unix(ref listener) => {
+				assert_eq!(listener.local_addr().unwrap().as_pathname(), Some(path.as_path()))
+			}
+			_ => panic!("expected a Unix listener"),
+		}
+
+		// sockets that can't accept connections are turned down, and left
+		// to their owners
+		let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+		assert!(unsafe { listeners_at(udp.as_raw_fd(), 1) }.is_err());
+		let stream = TcpStream::connect(addr).unwrap();
+		assert!(unsafe { listeners_at(stream.as_raw_fd(), 1) }.is_err());
+
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn counts_passed_sockets() {
+		assert_eq!(passed_count(Some("42"), Some("2"), 42).unwrap(), 2);
+		// variables for another process are ignored
+		assert_eq!(passed_count(Some("1"), Some("2"), 42).unwrap(), 0);
+		assert_eq!(passed_count(None, Some("2"), 42).unwrap(), 0);
+		assert_eq!(passed_count(Some("42"), None, 42).unwrap(), 0);
+		assert!(passed_count(Some("me"), Some("2"), 42).is_err());
+		assert!(passed_count(Some("42"), Some("-1"), 42).is_err());
+	}
+}
@@ -0,0 +1,222 @@
+//! Bind servers with control over the listening socket.
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, TcpListener};
+#[cfg(feature="rustls")]
+use std::sync::Arc;
+use socket2::{Socket, Domain, Type, Protocol};
+#[cfg(feature="ssl")]
+use openssl::ssl::SslAcceptor;
+#[cfg(feature="rustls")]
+use rustls;
+
+use super::{Server, NoSslAcceptor};
+
+/// Build servers with a builder-style API, for when the defaults of
+/// `Server::bind` don't do. This sets the options of the listening socket
+/// before it is bound:
+///
+/// ```rust,no_run
+/// use websocket::server::ServerBuilder;
+///
+/// // several threads each accepting on their own socket, the kernel
+/// // spreads connections over them
+/// let builder = ServerBuilder::new().reuse_port(true).backlog(1024);
+/// for _ in 0..4 {
+///     let server = builder.bind("0.0.0.0:8080").unwrap();
+///     // move `server` to an accept thread
+/// }
+///
+/// // one server for every address "localhost" resolves to, like both
+/// // 127.0.0.1 and ::1, the IPv6 socket taking no IPv4 connections
+/// let servers = ServerBuilder::new()
+///     .only_v6(true)
+///     .bind_all("localhost:8081")
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServerBuilder {
+	reuse_address: bool,
+	#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+	reuse_port: bool,
+	backlog: i32,
+	only_v6: Option<bool>,
+}
+
+impl Default for ServerBuilder {
+	fn default() -> Self {
+		ServerBuilder::new()
+	}
+}
+
+impl ServerBuilder {
+	/// Starts with the same settings `TcpListener::bind` uses, a backlog
+	/// of 128 and `SO_REUSEADDR` set on Unix.
+	pub fn new() -> Self {
+		ServerBuilder {
+			// on Windows SO_REUSEADDR lets another socket steal the port
+			reuse_address: cfg!(unix),
+			#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+			reuse_port: false,
+			backlog: 128,
+			only_v6: None,
+		}
+	}
+
+	/// Sets `SO_REUSEADDR`, on Unix this allows binding a port that still
+	/// has connections in the `TIME_WAIT` state, as after a restart.
+	pub fn reuse_address(mut self, reuse: bool) -> Self {
+		self.reuse_address = reuse;
+		self
+	}
+
+	/// Sets `SO_REUSEPORT`, allowing several sockets, in this process or
+	/// others, to listen on the same address and port. Each one gets a
+	/// share of the incoming connections on Linux.
+	#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+	pub fn reuse_port(mut self, reuse: bool) -> Self {
+		self.reuse_port = reuse;
+		self
+	}
+
+	/// How many connections can wait for `accept` before new ones are
+	/// refused, the operating system may cap this.
+	pub fn backlog(mut self, backlog: i32) -> Self {
+		self.backlog = backlog;
+		self
+	}
+
+	/// Whether IPv6 sockets only take IPv6 connections (`IPV6_V6ONLY`), or
+	/// are dual-stack and take IPv4 connections as mapped addresses too.
+	/// The operating system's default is kept if this isn't called, it has
+	/// no effect on IPv4 addresses.
+	pub fn only_v6(mut self, only_v6: bool) -> Self {
+		self.only_v6 = Some(only_v6);
+		self
+	}
+
+	/// Binds a listener to the first address `addr` resolves to that can be
+	/// bound, like `TcpListener::bind`.
+	pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<TcpListener> {
+		let mut last_error = None;
+		for addr in try!(addr.to_socket_addrs()) {
+			match self.listen_on(addr) {
+				Ok(listener) => return Ok(listener),
+				Err(e) => last_error = Some(e),
+			}
+		}
+		Err(last_error.unwrap_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+		}))
+	}
+
+	/// Binds a listener to every address `addr` resolves to, failing if
+	/// any of them can't be bound.
+	pub fn listen_all<A: ToSocketAddrs>(&self, addr: A) -> io::Result<Vec<TcpListener>> {
+		let mut listeners = Vec::new();
+		for addr in try!(addr.to_socket_addrs()) {
+			listeners.push(try!(self.listen_on(addr)));
+		}
+		if listeners.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput,
+			                          "could not resolve to any addresses"));
+		}
+		Ok(listeners)
+	}
+
+	fn listen_on(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+		let socket = try!(Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP)));
+		try!(socket.set_reuse_address(self.reuse_address));
+		#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+		{
+			if self.reuse_port {
+				try!(socket.set_reuse_port(true));
+			}
+		}
+		if let (Some(only_v6), true) = (self.only_v6, addr.is_ipv6()) {
+			try!(socket.set_only_v6(only_v6));
+		}
+		try!(socket.bind(&addr.into()));
+		try!(socket.listen(self.backlog));
+		Ok(socket.into())
+	}
+
+	/// Bind a Server to the first address `addr` resolves to that can be
+	/// bound, like `Server::bind`.
+	pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> io::Result<Server<NoSslAcceptor>> {
+		Ok(Server {
+		       listener: try!(self.listen(addr)),
+		       ssl_acceptor: NoSslAcceptor,
+		   })
+	}
+
+	/// Bind a Server to every address `addr` resolves to, each needs its
+	/// own thread to accept connections.
+	pub fn bind_all<A: ToSocketAddrs>(&self, addr: A) -> io::Result<Vec<Server<NoSslAcceptor>>> {
+		Ok(try!(self.listen_all(addr))
+		       .into_iter()
+		       .map(|listener| {
+			            Server {
+			                listener: listener,
+			                ssl_acceptor: NoSslAcceptor,
+			            }
+			           })
+		       .collect())
+	}
+
+	/// Bind a secure Server, like `Server::bind_secure`.
+	#[cfg(feature="ssl")]
+	pub fn bind_secure<A>(&self, addr: A, acceptor: SslAcceptor) -> io::Result<Server<SslAcceptor>>
+		where A: ToSocketAddrs
+	{
+		Ok(Server {
+		       listener: try!(self.listen(addr)),
+		       ssl_acceptor: acceptor,
+		   })
+	}
+
+	/// Bind a Server using rustls, like `Server::bind_rustls`.
+	#[cfg(feature="rustls")]
+	pub fn bind_rustls<A>(
+		&self,
+		addr: A,
+		config: Arc<rustls::ServerConfig>,
+	) -> io::Result<Server<Arc<rustls::ServerConfig>>>
+		where A: ToSocketAddrs
+	{
+		Ok(Server {
+		       listener: try!(self.listen(addr)),
+		       ssl_acceptor: config,
+		   })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpStream;
+
+	#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+	#[test]
+	fn reuse_port_shares_an_address() {
+		let builder = ServerBuilder::new().reuse_port(true).backlog(16);
+		let first = builder.listen("127.0.0.1:0").unwrap();
+		let addr = first.local_addr().unwrap();
+		let second = builder.listen(addr).unwrap();
+		assert_eq!(second.local_addr().unwrap(), addr);
+
+		// without it the port is taken
+		assert!(ServerBuilder::new().listen(addr).is_err());
+	}
+
+	#[test]
+	fn bind_all_addresses() {
+		let v4: SocketAddr = "127.0.0.1:0".parse().unwrap();
+		let servers = ServerBuilder::new().bind_all(&[v4, v4][..]).unwrap();
+		assert_eq!(servers.len(), 2);
+		for server in &servers {
+			let addr = server.local_addr().unwrap();
+			assert!(addr.ip().is_loopback());
+			TcpStream::connect(addr).unwrap();
+		}
+	}
+}
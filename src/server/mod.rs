@@ -2,13 +2,33 @@
 use std::net::{SocketAddr, ToSocketAddrs, TcpListener, TcpStream};
 use std::io;
 use std::convert::Into;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream, SocketAddr as UnixSocketAddr};
 #[cfg(feature="ssl")]
 use openssl::ssl::{SslStream, SslAcceptor};
+#[cfg(feature="rustls")]
+use std::sync::Arc;
+#[cfg(feature="rustls")]
+use rustls;
+#[cfg(feature="rustls")]
+use stream::RustlsServerStream;
 use stream::Stream;
 use self::upgrade::{WsUpgrade, IntoWs, Buffer};
 pub use self::upgrade::{Request, HyperIntoWsError};
 
 pub mod upgrade;
+pub mod request;
+pub use self::request::{UpgradeRequest, TrustedProxies};
+pub mod router;
+pub mod negotiate;
+pub mod builder;
+pub use self::builder::ServerBuilder;
+#[cfg(unix)]
+pub mod activation;
+#[cfg(feature="ssl")]
+pub mod tls;
 
 /// When a sever tries to accept a connection many things can go wrong.
 ///
@@ -48,6 +68,8 @@ pub trait OptionalSslAcceptor: Clone {}
 impl OptionalSslAcceptor for NoSslAcceptor {}
 #[cfg(feature="ssl")]
 impl OptionalSslAcceptor for SslAcceptor {}
+#[cfg(feature="rustls")]
+impl OptionalSslAcceptor for Arc<rustls::ServerConfig> {}
 
 /// Represents a WebSocket server which can work with either normal
 /// (non-secure) connections, or secure WebSocket connections.
@@ -124,8 +146,8 @@ impl OptionalSslAcceptor for SslAcceptor {}
 /// ```
 ///
 /// # A Hyper Server
-/// This crates comes with hyper integration out of the box, you can create a hyper
-/// server and serve websocket and HTTP **on the same port!**
+/// With the `hyper` feature enabled this crate integrates with hyper, you can create
+/// a hyper server and serve websocket and HTTP **on the same port!**
 /// check out the docs over at `websocket::server::upgrade::from_hyper` for an example.
 ///
 /// # A Custom Server
@@ -133,10 +155,10 @@ impl OptionalSslAcceptor for SslAcceptor {}
 /// All it takes is implementing the `IntoWs` trait for your server's streams,
 /// then calling `.into_ws()` on them.
 /// check out the docs over at `websocket::server::upgrade` for more.
-pub struct Server<S>
+pub struct Server<S, L = TcpListener>
 	where S: OptionalSslAcceptor
 {
-	listener: TcpListener,
+	listener: L,
 	ssl_acceptor: S,
 }
 
@@ -160,7 +182,9 @@ impl<S> Server<S>
 
 #[cfg(feature="ssl")]
 impl Server<SslAcceptor> {
-	/// Bind this Server to this socket, utilising the given SslContext
+	/// Bind this Server to this socket, utilising the given SslContext.
+	/// To choose certificates by hostname or replace them while the server
+	/// runs, pass the acceptor of a `server::tls::SniAcceptor`.
 	pub fn bind_secure<A>(addr: A, acceptor: SslAcceptor) -> io::Result<Self>
 		where A: ToSocketAddrs
 	{
@@ -170,6 +194,15 @@ impl Server<SslAcceptor> {
 		   })
 	}
 
+	/// Make a secure Server out of a listener that is already bound, like
+	/// one inherited from a parent process or systemd.
+	pub fn from_listener_secure(listener: TcpListener, acceptor: SslAcceptor) -> Self {
+		Server {
+			listener: listener,
+			ssl_acceptor: acceptor,
+		}
+	}
+
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<SslStream<TcpStream>> {
 		let stream = match self.listener.accept() {
@@ -227,8 +260,120 @@ impl Iterator for Server<SslAcceptor> {
 	}
 }
 
+#[cfg(feature="rustls")]
+impl Server<Arc<rustls::ServerConfig>> {
+	/// Bind this Server to this socket, using rustls with the given
+	/// configuration for TLS.
+	///
+	/// ```rust,no_run
+	/// # extern crate rustls;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use std::sync::Arc;
+	/// use websocket::Server;
+	///
+	/// # fn load_config() -> rustls::ServerConfig { unimplemented!() }
+	/// // a config with the server's certificate chain and private key
+	/// let config: rustls::ServerConfig = load_config();
+	///
+	/// let server = Server::bind_rustls("127.0.0.1:443", Arc::new(config)).unwrap();
+	///
+	/// for connection in server.filter_map(Result::ok) {
+	///     let client = connection.accept().unwrap();
+	/// }
+	/// # }
+	/// ```
+	pub fn bind_rustls<A>(addr: A, config: Arc<rustls::ServerConfig>) -> io::Result<Self>
+		where A: ToSocketAddrs
+	{
+		Ok(Server {
+		       listener: try!(TcpListener::bind(&addr)),
+		       ssl_acceptor: config,
+		   })
+	}
+
+	/// Make a Server using rustls out of a listener that is already bound.
+	pub fn from_listener_rustls(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> Self {
+		Server {
+			listener: listener,
+			ssl_acceptor: config,
+		}
+	}
+
+	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	pub fn accept(&mut self) -> AcceptResult<RustlsServerStream> {
+		let stream = match self.listener.accept() {
+			Ok(s) => s.0,
+			Err(e) => {
+				return Err(InvalidConnection {
+				               stream: None,
+				               parsed: None,
+				               buffer: None,
+				               error: e.into(),
+				           })
+			}
+		};
+
+		let stream = match accept_rustls(self.ssl_acceptor.clone(), stream) {
+			Ok(s) => s,
+			Err(err) => {
+				return Err(InvalidConnection {
+				               stream: None,
+				               parsed: None,
+				               buffer: None,
+				               error: err.into(),
+				           })
+			}
+		};
+
+		match stream.into_ws() {
+			Ok(u) => Ok(u),
+			Err((s, r, b, e)) => {
+				Err(InvalidConnection {
+				        stream: Some(s),
+				        parsed: r,
+				        buffer: b,
+				        error: e.into(),
+				    })
+			}
+		}
+	}
+
+	/// Changes whether the Server is in nonblocking mode.
+	///
+	/// If it is in nonblocking mode, accept() will return an error instead of blocking when there
+	/// are no incoming connections.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.listener.set_nonblocking(nonblocking)
+	}
+}
+
+#[cfg(feature="rustls")]
+impl Iterator for Server<Arc<rustls::ServerConfig>> {
+	type Item = AcceptResult<RustlsServerStream>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		Some(self.accept())
+	}
+}
+
+#[cfg(feature="rustls")]
+fn accept_rustls(
+	config: Arc<rustls::ServerConfig>,
+	stream: TcpStream,
+) -> io::Result<RustlsServerStream> {
+	let connection = try!(rustls::ServerConnection::new(config)
+	                          .map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+	let mut stream = rustls::StreamOwned::new(connection, stream);
+	while stream.conn.is_handshaking() {
+		try!(stream.conn.complete_io(&mut stream.sock));
+	}
+	Ok(stream)
+}
+
 impl Server<NoSslAcceptor> {
-	/// Bind this Server to this socket
+	/// Bind this Server to this socket, use a `ServerBuilder` to set
+	/// socket options like `SO_REUSEPORT` or the listen backlog.
 	pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
 		Ok(Server {
 		       listener: try!(TcpListener::bind(&addr)),
@@ -236,6 +381,24 @@ impl Server<NoSslAcceptor> {
 		   })
 	}
 
+	/// Make a Server out of a listener that is already bound, like one
+	/// inherited from a parent process or passed in by systemd (see the
+	/// `activation` module).
+	///
+	/// ```rust,no_run
+	/// use std::net::TcpListener;
+	/// use websocket::Server;
+	///
+	/// let listener = TcpListener::bind("127.0.0.1:1234").unwrap();
+	/// let server = Server::from_listener(listener);
+	/// ```
+	pub fn from_listener(listener: TcpListener) -> Self {
+		Server {
+			listener: listener,
+			ssl_acceptor: NoSslAcceptor,
+		}
+	}
+
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TcpStream> {
 		let stream = match self.listener.accept() {
@@ -271,3 +434,337 @@ impl Iterator for Server<NoSslAcceptor> {
 		Some(self.accept())
 	}
 }
+
+#[cfg(unix)]
+impl Server<NoSslAcceptor, UnixListener> {
+	/// Bind this Server to a Unix domain socket at the given path.
+	/// The socket file is created by binding, so the path must not exist yet.
+	///
+	/// ```rust,no_run
+	/// use websocket::Server;
+	///
+	/// let server = Server::bind_unix("/run/app.sock").unwrap();
+	///
+	/// for connection in server.filter_map(Result::ok) {
+	///     let client = connection.accept().unwrap();
+	///     println!("connected to {:?}", client.peer_cred());
+	/// }
+	/// ```
+	pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		Ok(Server {
+		       listener: try!(UnixListener::bind(path)),
+		       ssl_acceptor: NoSslAcceptor,
+		   })
+	}
+
+	/// Make a Server out of a Unix listener that is already bound.
+	pub fn from_unix_listener(listener: UnixListener) -> Self {
+		Server {
+			listener: listener,
+			ssl_acceptor: NoSslAcceptor,
+		}
+	}
+
+	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	pub fn accept(&mut self) -> AcceptResult<UnixStream> {
+		let stream = match self.listener.accept() {
+			Ok(s) => s.0,
+			Err(e) => {
+				return Err(InvalidConnection {
+				               stream: None,
+				               parsed: None,
+				               buffer: None,
+				               error: e.into(),
+				           })
+			}
+		};
+
+		match stream.into_ws() {
+			Ok(u) => Ok(u),
+			Err((s, r, b, e)) => {
+				Err(InvalidConnection {
+				        stream: Some(s),
+				        parsed: r,
+				        buffer: b,
+				        error: e.into(),
+				    })
+			}
+		}
+	}
+
+	/// Get the socket address of this server
+	pub fn local_addr(&self) -> io::Result<UnixSocketAddr> {
+		self.listener.local_addr()
+	}
+
+	/// Create a new independently owned handle to the underlying socket.
+	pub fn try_clone(&self) -> io::Result<Self> {
+		let inner = try!(self.listener.try_clone());
+		Ok(Server {
+		       listener: inner,
+		       ssl_acceptor: NoSslAcceptor,
+		   })
+	}
+
+	/// Changes whether the Server is in nonblocking mode.
+	///
+	/// If it is in nonblocking mode, accept() will return an error instead of blocking when there
+	/// are no incoming connections.
+	pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.listener.set_nonblocking(nonblocking)
+	}
+}
+
+#[cfg(unix)]
+impl Iterator for Server<NoSslAcceptor, UnixListener> {
+	type Item = AcceptResult<UnixStream>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		Some(self.accept())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use client::ClientBuilder;
+	use message::Message;
+
+	#[cfg(unix)]
+	#[test]
+	fn unix_socket_round_trip() {
+		use std::env;
+		use std::fs;
+		use std::process;
+
+		let path = env::temp_dir().join(format!("websocket-test-{}.sock", process::id()));
+		let _ = fs::remove_file(&path);
+		let mut server = Server::bind_unix(&path).unwrap();
+
+		let url = format!("ws+unix://{}:/echo", path.display());
+		let remote = thread::spawn(move || {
+			let mut client = ClientBuilder::new(&url).unwrap().connect_unix().unwrap();
+			client.send_message(&Message::text("hello")).unwrap();
+			let reply: Message = client.recv_message().unwrap();
+			client.shutdown().unwrap();
+			String::from_utf8(reply.payload.into_owned()).unwrap()
+		});
+
+		let upgrade = server.accept().ok().unwrap();
+		assert_eq!(upgrade.request.subject.1.to_string(), "/echo");
+		let mut client = upgrade.accept().ok().unwrap();
+		let cred = client.peer_cred().unwrap();
+		assert_eq!(cred.pid, Some(process::id() as i32));
+
+		let message: Message = client.recv_message().unwrap();
+		client.send_message(&message).unwrap();
+		assert_eq!(remote.join().unwrap(), "hello");
+
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn keeps_the_upgrade_request() {
+		use std::net::IpAddr;
+		use header::Headers;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://127.0.0.1:{}/rooms/lobby?nick=ada%20l", server.local_addr().unwrap().port());
+		let remote = thread::spawn(move || {
+			let mut headers = Headers::new();
+			headers.set_raw("Cookie", vec![b"session=abc; theme=dark".to_vec()]);
+			headers.set_raw("X-Forwarded-For", vec![b"203.0.113.9".to_vec()]);
+			let mut client = ClientBuilder::new(&url)
+				.unwrap()
+				.custom_headers(&headers)
+				.connect_insecure()
+				.unwrap();
+			assert!(client.request().is_none());
+			let _: Message = client.recv_message().unwrap();
+		});
+
+		let upgrade = server.accept().ok().unwrap();
+		let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+		let client = upgrade.trust_proxies(TrustedProxies::Only(vec![loopback]))
+		                    .accept()
+		                    .ok()
+		                    .unwrap();
+		{
+			let request = client.request().unwrap();
+			assert_eq!(request.path(), "/rooms/lobby");
+			assert_eq!(request.query_param("nick"), Some("ada l".to_owned()));
+			assert_eq!(request.cookie("session"), Some("abc"));
+		}
+		assert_eq!(client.client_addr().unwrap(), "203.0.113.9".parse::<IpAddr>().unwrap());
+
+		let (receiver, mut sender) = client.split().unwrap();
+		assert_eq!(receiver.request().unwrap().path(), "/rooms/lobby");
+		assert_eq!(sender.request().unwrap().cookie("theme"), Some("dark"));
+		sender.send_message(&Message::text("bye")).unwrap();
+		remote.join().unwrap();
+	}
+
+	#[cfg(feature = "rustls")]
+	fn rustls_configs() -> (rustls::ServerConfig, rustls::ClientConfig, String) {
+		use rcgen;
+		use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+		let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+		let cert = certified.cert.der().clone();
+		let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+		let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+		let server = rustls::ServerConfig::builder_with_provider(provider.clone())
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_no_client_auth()
+			.with_single_cert(vec![cert.clone()], PrivateKeyDer::Pkcs8(key))
+			.unwrap();
+
+		let mut roots = rustls::RootCertStore::empty();
+		roots.add(cert).unwrap();
+		let client = rustls::ClientConfig::builder_with_provider(provider)
+			.with_safe_default_protocol_versions()
+			.unwrap()
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+
+		(server, client, certified.cert.pem())
+	}
+
+	#[cfg(feature = "rustls")]
+	#[test]
+	fn rustls_round_trip() {
+		let (server_config, client_config, _) = rustls_configs();
+		let mut server = Server::bind_rustls("127.0.0.1:0", Arc::new(server_config)).unwrap();
+		let url = format!("wss://localhost:{}/tls", server.local_addr().unwrap().port());
+
+		let remote = thread::spawn(move || {
+			let mut client = ClientBuilder::new(&url)
+				.unwrap()
+				.connect_secure_rustls(Some(Arc::new(client_config)))
+				.unwrap();
+			client.send_message(&Message::text("secret")).unwrap();
+			let reply: Message = client.recv_message().unwrap();
+			String::from_utf8(reply.payload.into_owned()).unwrap()
+		});
+
+		let upgrade = server.next().unwrap().ok().unwrap();
+		assert_eq!(upgrade.request.subject.1.to_string(), "/tls");
+		let mut client = upgrade.accept().ok().unwrap();
+		let message: Message = client.recv_message().unwrap();
+		client.send_message(&message).unwrap();
+		assert_eq!(remote.join().unwrap(), "secret");
+	}
+
+	#[cfg(feature = "rustls")]
+	#[test]
+	fn rustls_split_shared() {
+		let (server_config, client_config, _) = rustls_configs();
+		let mut server = Server::bind_rustls("127.0.0.1:0", Arc::new(server_config)).unwrap();
+		let url = format!("wss://localhost:{}", server.local_addr().unwrap().port());
+
+		let remote = thread::spawn(move || {
+			let client = ClientBuilder::new(&url)
+				.unwrap()
+				.connect_secure_rustls(Some(Arc::new(client_config)))
+				.unwrap();
+			let (mut receiver, sender) = client.split_shared().unwrap();
+			let writers: Vec<_> = (0..4u8)
+				.map(|n| {
+					let mut sender = sender.clone();
+					thread::spawn(move || for _ in 0..10 {
+					                  sender.send_message(&Message::binary(vec![n; 20000])).unwrap();
+					              })
+				})
+				.collect();
+			for writer in writers {
+				writer.join().unwrap();
+			}
+			// the last message can't be stuck in the TLS buffer
+			(0..40)
+				.map(|_| {
+					let message: Message = receiver.recv_message().unwrap();
+					message.payload.into_owned()
+				})
+				.collect::<Vec<_>>()
+		});
+
+		let mut client = server.accept().ok().unwrap().accept().ok().unwrap();
+		for _ in 0..40 {
+			let message: Message = client.recv_message().unwrap();
+			client.send_message(&message).unwrap();
+		}
+		let received = remote.join().unwrap();
+		assert!(received.iter().all(|payload| payload.len() == 20000 && payload.iter().all(|b| *b == payload[0])));
+	}
+
+	#[cfg(feature = "rustls")]
+	#[test]
+	fn rustls_rejects_unknown_certificate() {
+		let (server_config, _, _) = rustls_configs();
+		let mut server = Server::bind_rustls("127.0.0.1:0", Arc::new(server_config)).unwrap();
+		let url = format!("wss://localhost:{}", server.local_addr().unwrap().port());
+
+		// the self-signed certificate isn't in the default root store
+		let remote = thread::spawn(move || ClientBuilder::new(&url).unwrap().connect_rustls(None).is_err());
+
+		assert!(server.accept().is_err());
+		assert!(remote.join().unwrap());
+	}
+
+	#[cfg(feature = "rustls")]
+	#[test]
+	fn rustls_client_tls_settings() {
+		use std::env;
+		use std::fs;
+		use std::process;
+		use ring::digest;
+
+		let (server_config, _, pem) = rustls_configs();
+		let path = env::temp_dir().join(format!("websocket-test-{}.pem", process::id()));
+		fs::write(&path, pem).unwrap();
+		let mut server = Server::bind_rustls("127.0.0.1:0", Arc::new(server_config)).unwrap();
+		let url = format!("wss://localhost:{}", server.local_addr().unwrap().port());
+
+		let ca = path.clone();
+		let remote = thread::spawn(move || {
+			// trust the self-signed certificate from its file
+			let client = ClientBuilder::new(&url)
+				.unwrap()
+				.add_root_certificate_pem(&ca)
+				.connect_secure_rustls(None)
+				.unwrap();
+			let session = client.tls_session().unwrap();
+			let mut pin = [0; 32];
+			pin.copy_from_slice(digest::digest(&digest::SHA256, &session.peer_certificates[0]).as_ref());
+
+			// or accept any certificate, as long as it is the pinned one
+			let pinned = ClientBuilder::new(&url)
+				.unwrap()
+				.danger_accept_invalid_certs(true)
+				.pin_certificate_sha256(pin)
+				.connect_rustls(None);
+			let mismatched = ClientBuilder::new(&url)
+				.unwrap()
+				.danger_accept_invalid_certs(true)
+				.pin_certificate_sha256([0; 32])
+				.connect_rustls(None);
+			(session, pinned.is_ok(), mismatched.is_err())
+		});
+
+		for _ in 0..2 {
+			server.accept().ok().unwrap().accept().ok().unwrap();
+		}
+		// the last client hangs up right after the handshake
+		assert!(server.accept().is_err());
+
+		let (session, pinned, mismatched) = remote.join().unwrap();
+		assert_eq!(session.protocol, "TLSv1.3");
+		assert!(!session.cipher.is_empty());
+		assert!(pinned);
+		assert!(mismatched);
+		fs::remove_file(&path).unwrap();
+	}
+}
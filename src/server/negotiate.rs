@@ -0,0 +1,235 @@
+//! Choose the subprotocol of a connection and hand it to the handler of
+//! that protocol.
+use handshake::StatusCode;
+use header::{Headers, WebSocketProtocol};
+use client::Client;
+use stream::Stream;
+use super::router::Unrouted;
+use super::upgrade::WsUpgrade;
+
+/// Picks one of the subprotocols the server supports among the ones a
+/// client offers and hands the accepted client to the handler registered
+/// for it. Protocols are preferred in the order they were added, unless
+/// `prefer_client_order` is set. RFC 6455 lets a server choose at most one
+/// protocol, so exactly the chosen one is sent back.
+///
+/// A client that offers none of the protocols is rejected with 400 Bad
+/// Request, unless there is a `without_protocol` handler, which gets it
+/// accepted without a protocol.
+///
+/// ```rust,no_run
+/// use websocket::Server;
+/// use websocket::server::negotiate::Negotiator;
+///
+/// let negotiator = Negotiator::new()
+///     .protocol("graphql-transport-ws", |client| {
+///         // ...
+///     })
+///     .protocol("graphql-ws", |client| {
+///         // the legacy protocol
+///     })
+///     .protocol("mqtt", |client| {
+///         // ...
+///     });
+///
+/// let server = Server::bind("0.0.0.0:8080").unwrap();
+/// for upgrade in server.filter_map(Result::ok) {
+///     if let Err(unnegotiated) = negotiator.dispatch(upgrade) {
+///         println!("no protocol for {:?}", unnegotiated.request);
+///     }
+/// }
+/// ```
+///
+/// With a `Router` each route can have its own protocols, the negotiator
+/// moving into the route:
+///
+/// ```rust,no_run
+/// # use websocket::server::negotiate::Negotiator;
+/// use std::net::TcpStream;
+/// use websocket::server::router::Router;
+///
+/// let graphql = Negotiator::new()
+///     .protocol("graphql-transport-ws", |client| {})
+///     .protocol("graphql-ws", |client| {});
+/// let router: Router<TcpStream> = Router::new()
+///     .route("/graphql", move |upgrade, _| drop(graphql.dispatch(upgrade)));
+/// ```
+pub struct Negotiator<S, T = ()>
+	where S: Stream
+{
+	protocols: Vec<(String, Box<Fn(Client<S>) -> T + Send + Sync>)>,
+	without: Option<Box<Fn(Client<S>) -> T + Send + Sync>>,
+	client_order: bool,
+}
+
+impl<S, T> Negotiator<S, T>
+    where S: Stream
+{
+	/// A negotiator without protocols, rejecting every client.
+	pub fn new() -> Self {
+		Negotiator {
+			protocols: Vec::new(),
+			without: None,
+			client_order: false,
+		}
+	}
+
+	/// Adds a protocol the server supports, less preferred than the ones
+	/// added before, and the handler of the clients that use it.
+	pub fn protocol<P, F>(mut self, protocol: P, handler: F) -> Self
+		where P: Into<String>,
+		      F: Fn(Client<S>) -> T + Send + Sync + 'static
+	{
+		self.protocols.push((protocol.into(), Box::new(handler)));
+		self
+	}
+
+	/// Accepts clients that offer none of the protocols, or none at all,
+	/// without a protocol and hands them to `handler`.
+	pub fn without_protocol<F>(mut self, handler: F) -> Self
+		where F: Fn(Client<S>) -> T + Send + Sync + 'static
+	{
+		self.without = Some(Box::new(handler));
+		self
+	}
+
+	/// Whether to prefer the protocols in the order the client offers them,
+	/// instead of the order they were added in. Defaults to false.
+	pub fn prefer_client_order(mut self, client_order: bool) -> Self {
+		self.client_order = client_order;
+		self
+	}
+
+	/// The protocol to use with a client that offers `offered`, `None` if
+	/// it offers none of the supported ones.
+	pub fn choose<'a>(&'a self, offered: &[String]) -> Option<&'a str> {
+		self.choice(offered).map(|index| &self.protocols[index].0[..])
+	}
+
+	fn choice(&self, offered: &[String]) -> Option<usize> {
+		let supported = |protocol: &String| self.protocols.iter().position(|supported| supported.0 == *protocol);
+		if self.client_order {
+			offered.iter().filter_map(supported).next()
+		} else {
+			offered.iter().filter_map(supported).min()
+		}
+	}
+
+	/// Accepts the upgrade with the protocol `choose` picks and hands the
+	/// client to the handler of the protocol. A client without a protocol
+	/// to use is rejected with 400 Bad Request, unless there's a
+	/// `without_protocol` handler. Failing to write the answer is an
+	/// `Unrouted` too, with the status that couldn't be sent.
+	pub fn dispatch(&self, mut upgrade: WsUpgrade<S>) -> Result<T, Unrouted<S>> {
+		let (upgrade, handler) = match self.choice(upgrade.protocols()) {
+			Some(index) => {
+				let &(ref protocol, ref handler) = &self.protocols[index];
+				(upgrade.use_protocol(&protocol[..]), handler)
+			}
+			None => {
+				match self.without {
+					Some(ref handler) => {
+						upgrade.headers.remove::<WebSocketProtocol>();
+						(upgrade, handler)
+					}
+					None => {
+						let request = upgrade.request.clone();
+						let (stream, error) = match upgrade.reject_with_status(StatusCode::BadRequest,
+						                                                       &Headers::new()) {
+							Ok(stream) => (stream, None),
+							Err((stream, error)) => (stream, Some(error)),
+						};
+						return Err(Unrouted {
+						               status: StatusCode::BadRequest,
+						               request: Some(request),
+						               stream: Some(stream),
+						               error: error,
+						           });
+					}
+				}
+			}
+		};
+
+		let request = upgrade.request.clone();
+		match upgrade.accept() {
+			Ok(client) => Ok(handler(client)),
+			Err((stream, error)) => {
+				Err(Unrouted {
+				        status: StatusCode::SwitchingProtocols,
+				        request: Some(request),
+				        stream: Some(stream),
+				        error: Some(error),
+				    })
+			}
+		}
+	}
+}
+
+impl<S, T> Default for Negotiator<S, T>
+    where S: Stream
+{
+	fn default() -> Self {
+		Negotiator::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use stream::ReadWritePair;
+	use server::upgrade::IntoWs;
+
+	type Pair = ReadWritePair<Cursor<Vec<u8>>, Vec<u8>>;
+
+	fn upgrade(protocols: &str) -> WsUpgrade<Pair> {
+		let mut request = "GET /graphql HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\n\
+		                   Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                   Sec-WebSocket-Version: 13\r\n"
+			.to_owned();
+		if !protocols.is_empty() {
+			request.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocols));
+		}
+		request.push_str("\r\n");
+		ReadWritePair(Cursor::new(request.into_bytes()), Vec::new()).into_ws().ok().unwrap()
+	}
+
+	fn protocols(client: &Client<Pair>) -> Vec<String> {
+		let response = String::from_utf8(client.stream_ref().1.clone()).unwrap();
+		response.lines()
+		        .filter(|line| line.starts_with("Sec-WebSocket-Protocol:"))
+		        .map(str::to_owned)
+		        .collect()
+	}
+
+	#[test]
+	fn chooses_one_protocol() {
+		let negotiator = Negotiator::new()
+			.protocol("graphql-transport-ws", |client| ("new", protocols(&client)))
+			.protocol("graphql-ws", |client| ("legacy", protocols(&client)));
+
+		let offered = vec!["graphql-ws".to_owned(), "graphql-transport-ws".to_owned()];
+		assert_eq!(negotiator.choose(&offered), Some("graphql-transport-ws"));
+		assert_eq!(negotiator.dispatch(upgrade("graphql-ws, graphql-transport-ws")).ok(),
+		           Some(("new", vec!["Sec-WebSocket-Protocol: graphql-transport-ws".to_owned()])));
+		assert_eq!(negotiator.dispatch(upgrade("mqtt, graphql-ws")).ok(),
+		           Some(("legacy", vec!["Sec-WebSocket-Protocol: graphql-ws".to_owned()])));
+
+		let negotiator = negotiator.prefer_client_order(true);
+		assert_eq!(negotiator.choose(&offered), Some("graphql-ws"));
+
+		let unnegotiated = negotiator.dispatch(upgrade("mqtt")).err().unwrap();
+		assert_eq!(unnegotiated.status, StatusCode::BadRequest);
+		assert!(negotiator.dispatch(upgrade("")).is_err());
+	}
+
+	#[test]
+	fn accepts_without_protocol() {
+		let negotiator = Negotiator::new()
+			.protocol("mqtt", |_| "mqtt".to_owned())
+			.without_protocol(|client| format!("plain {:?}", protocols(&client)));
+		assert_eq!(negotiator.dispatch(upgrade("")).ok(), Some("plain []".to_owned()));
+		assert_eq!(negotiator.dispatch(upgrade("stomp")).ok(), Some("plain []".to_owned()));
+		assert_eq!(negotiator.dispatch(upgrade("stomp, mqtt")).ok(), Some("mqtt".to_owned()));
+	}
+}
@@ -0,0 +1,237 @@
+//! The upgrade request a server-side client was accepted with.
+use std::net::{IpAddr, SocketAddr};
+use url::form_urlencoded;
+
+use handshake::HttpVersion;
+use header::{Headers, Cookie};
+use super::upgrade::Request;
+
+/// Which peers may say, with `Forwarded` or `X-Forwarded-For`, who the
+/// client behind them is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrustedProxies {
+	/// No one, the forwarding headers are ignored.
+	None,
+	/// Every peer, for a server that can only be reached through proxies.
+	All,
+	/// The proxies with these addresses.
+	Only(Vec<IpAddr>),
+}
+
+impl TrustedProxies {
+	/// Whether `addr` is a trusted proxy.
+	pub fn trusts(&self, addr: &IpAddr) -> bool {
+		match *self {
+			TrustedProxies::None => false,
+			TrustedProxies::All => true,
+			TrustedProxies::Only(ref addrs) => addrs.contains(addr),
+		}
+	}
+}
+
+impl Default for TrustedProxies {
+	fn default() -> Self {
+		TrustedProxies::None
+	}
+}
+
+/// The upgrade request a server-side `Client` was accepted with, kept for
+/// handlers to find out what the connection is for.
+///
+/// ```rust,no_run
+/// use websocket::Server;
+///
+/// let mut server = Server::bind("127.0.0.1:2794").unwrap();
+/// let client = server.accept().ok().unwrap().accept().unwrap();
+///
+/// let request = client.request().unwrap();
+/// if request.path() == "/rooms" {
+///     let room = request.query_param("name");
+///     let session = request.cookie("session");
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct UpgradeRequest {
+	/// The request as it was read.
+	pub request: Request,
+	/// Which peers may say who the client behind them is, for
+	/// `client_addr`.
+	pub trusted_proxies: TrustedProxies,
+}
+
+impl UpgradeRequest {
+	/// The HTTP version of the request.
+	pub fn version(&self) -> HttpVersion {
+		self.request.version
+	}
+
+	/// The headers of the request.
+	pub fn headers(&self) -> &Headers {
+		&self.request.headers
+	}
+
+	/// The path the request was for, without the query.
+	pub fn path(&self) -> &str {
+		self.request.subject.1.path()
+	}
+
+	/// The query of the request, if there is one, as it was sent.
+	pub fn query(&self) -> Option<&str> {
+		self.request.subject.1.query()
+	}
+
+	/// The decoded name and value pairs of the query, in order.
+	pub fn query_pairs(&self) -> Vec<(String, String)> {
+		form_urlencoded::parse(self.query().unwrap_or("").as_bytes())
+			.into_owned()
+			.collect()
+	}
+
+	/// The decoded value of the first query parameter called `name`.
+	pub fn query_param(&self, name: &str) -> Option<String> {
+		self.query_pairs()
+		    .into_iter()
+		    .find(|pair| pair.0 == name)
+		    .map(|pair| pair.1)
+	}
+
+	/// The name and value pairs of the `Cookie` headers, in order.
+	pub fn cookies(&self) -> Vec<(&str, &str)> {
+		self.request
+		    .headers
+		    .get::<Cookie>()
+		    .map_or(Vec::new(), |cookies| {
+			cookies.0
+			       .iter()
+			       .map(|cookie| match cookie.find('=') {
+			                Some(eq) => (cookie[..eq].trim(), cookie[eq + 1..].trim()),
+			                None => ("", cookie.trim()),
+			            })
+			       .collect()
+			})
+	}
+
+	/// The value of the first cookie called `name`.
+	pub fn cookie(&self, name: &str) -> Option<&str> {
+		self.cookies()
+		    .into_iter()
+		    .find(|cookie| cookie.0 == name)
+		    .map(|cookie| cookie.1)
+	}
+
+	/// The address of the client, given the address of the peer of the
+	/// connection. When the peer is a trusted proxy, the `Forwarded` header
+	/// (or `X-Forwarded-For` without one) is followed back to the first
+	/// address that isn't a trusted proxy.
+	pub fn client_addr(&self, peer: IpAddr) -> IpAddr {
+		let mut client = peer;
+		if !self.trusted_proxies.trusts(&client) {
+			return client;
+		}
+		for addr in self.forwarded_for().into_iter().rev() {
+			match addr {
+				Some(addr) => client = addr,
+				// hidden or garbled, the proxy is as far as it goes
+				None => break,
+			}
+			if !self.trusted_proxies.trusts(&client) {
+				break;
+			}
+		}
+		client
+	}
+
+	/// The addresses the request was forwarded for, the first being the
+	/// client as the first proxy saw it.
+	fn forwarded_for(&self) -> Vec<Option<IpAddr>> {
+		let headers = &self.request.headers;
+		if let Some(values) = headers.get_raw("Forwarded") {
+			return values.iter()
+			             .flat_map(|value| String::from_utf8_lossy(value).split(',').map(str::to_owned).collect::<Vec<_>>())
+			             .filter_map(|element| {
+				element.split(';')
+				       .filter_map(|pair| {
+					let mut pair = pair.splitn(2, '=');
+					match (pair.next(), pair.next()) {
+						(Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("for") => {
+							Some(node_addr(value.trim().trim_matches('"')))
+						}
+						_ => None,
+					}
+				})
+				       .next()
+			})
+			             .collect();
+		}
+		headers.get_raw("X-Forwarded-For")
+		       .map_or(Vec::new(), |values| {
+			values.iter()
+			      .flat_map(|value| String::from_utf8_lossy(value).split(',').map(node_addr).collect::<Vec<_>>())
+			      .collect()
+			})
+	}
+}
+
+/// The IP address of a node like `192.0.2.60`, `192.0.2.60:4711`,
+/// `[2001:db8::1]:4711` or `2001:db8::1`.
+fn node_addr(node: &str) -> Option<IpAddr> {
+	let node = node.trim();
+	node.parse::<IpAddr>()
+	    .ok()
+	    .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+	    .or_else(|| node.trim_start_matches('[').trim_end_matches(']').parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use handshake::{Incoming, Method, RequestUri};
+
+	fn request(target: &str, headers: &[(&str, &str)]) -> UpgradeRequest {
+		let mut raw = Headers::new();
+		for &(name, value) in headers {
+			raw.append_raw(name.to_owned(), value.as_bytes().to_vec());
+		}
+		UpgradeRequest {
+			request: Incoming {
+				version: HttpVersion::Http11,
+				subject: (Method::Get, RequestUri::AbsolutePath(target.to_owned())),
+				headers: raw,
+			},
+			trusted_proxies: TrustedProxies::None,
+		}
+	}
+
+	#[test]
+	fn path_query_and_cookies() {
+		let request = request("/rooms/lobby?name=caf%C3%A9&tag=a&tag=b+c",
+		                      &[("Cookie", "session=abc; theme=dark")]);
+		assert_eq!(request.path(), "/rooms/lobby");
+		assert_eq!(request.query(), Some("name=caf%C3%A9&tag=a&tag=b+c"));
+		assert_eq!(request.query_param("name"), Some("café".to_owned()));
+		assert_eq!(request.query_pairs()[2], ("tag".to_owned(), "b c".to_owned()));
+		assert_eq!(request.cookies(), [("session", "abc"), ("theme", "dark")]);
+		assert_eq!(request.cookie("theme"), Some("dark"));
+		assert_eq!(request.cookie("missing"), None);
+	}
+
+	#[test]
+	fn client_addr_behind_proxies() {
+		let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+		let mut request = request("/",
+		                          &[("X-Forwarded-For", "198.51.100.7, 10.0.0.2"),
+		                            ("Forwarded", "for=192.0.2.60;proto=https, for=\"[2001:db8::17]:4711\"")]);
+		// not trusted, the headers could say anything
+		assert_eq!(request.client_addr(proxy), proxy);
+
+		request.trusted_proxies = TrustedProxies::Only(vec![proxy]);
+		assert_eq!(request.client_addr(proxy), "2001:db8::17".parse::<IpAddr>().unwrap());
+		request.trusted_proxies = TrustedProxies::All;
+		assert_eq!(request.client_addr(proxy), "192.0.2.60".parse::<IpAddr>().unwrap());
+
+		request.request.headers.remove_raw("Forwarded");
+		request.trusted_proxies = TrustedProxies::Only(vec![proxy, "10.0.0.2".parse().unwrap()]);
+		assert_eq!(request.client_addr(proxy), "198.51.100.7".parse::<IpAddr>().unwrap());
+	}
+}
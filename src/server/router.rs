@@ -0,0 +1,405 @@
+//! Dispatch upgrade requests to handlers by host and path.
+use std::fmt;
+use std::io::Error as IoError;
+use url::percent_encoding::percent_decode;
+
+use handshake::{HttpVersion, StatusCode};
+use header::{Headers, Host, WebSocketVersion, Upgrade, Protocol, ProtocolName, Connection,
+             ConnectionOption};
+use stream::Stream;
+use unicase::UniCase;
+use super::{InvalidConnection, Request, HyperIntoWsError};
+use super::upgrade::WsUpgrade;
+
+/// Sends upgrade requests to the handler of the first route their host and
+/// path match, in the order the routes were added, and answers the ones no
+/// route matches with 404 Not Found.
+///
+/// Path patterns are made of segments separated by `/`. A segment like
+/// `{symbol}` matches any one non-empty segment and captures it, a last
+/// segment like `{*rest}` captures everything after it (possibly nothing).
+/// Host patterns are a host name, or `*.` and a domain to match the
+/// subdomains of the domain. The query and the port aren't matched.
+///
+/// ```rust,no_run
+/// use websocket::Server;
+/// use websocket::server::router::Router;
+///
+/// let router = Router::new()
+///     .route("/chat", |upgrade, _| {
+///         let client = upgrade.use_protocol("chat").accept().unwrap();
+///         // ...
+///     })
+///     .route("/feed/{symbol}", |upgrade, params| {
+///         let symbol = params.get("symbol").unwrap().to_owned();
+///         let client = upgrade.accept().unwrap();
+///         // ...
+///     })
+///     .route_host("admin.example.com", "/admin", |upgrade, _| {
+///         // ...
+///     });
+///
+/// let server = Server::bind("0.0.0.0:8080").unwrap();
+/// for connection in server {
+///     match connection {
+///         Ok(upgrade) => drop(router.dispatch(upgrade)),
+///         Err(invalid) => drop(router.reject_invalid(invalid)),
+///     }
+/// }
+/// ```
+pub struct Router<S, T = ()>
+	where S: Stream
+{
+	routes: Vec<Route<S, T>>,
+}
+
+struct Route<S, T>
+	where S: Stream
+{
+	host: Option<String>,
+	segments: Vec<Segment>,
+	handler: Box<Fn(WsUpgrade<S>, Params) -> T + Send + Sync>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+	Literal(String),
+	Param(String),
+	Rest(String),
+}
+
+/// The segments a route captured, decoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params {
+	params: Vec<(String, String)>,
+}
+
+impl Params {
+	/// The value captured by `{name}` or `{*name}`.
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.params
+		    .iter()
+		    .find(|param| param.0 == name)
+		    .map(|param| &param.1[..])
+	}
+
+	/// The names and values of the captured segments, in order.
+	pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, (String, String)> {
+		self.params.iter()
+	}
+}
+
+/// An upgrade request no route or, with a `Negotiator`, no protocol took,
+/// with what it was answered with.
+pub struct Unrouted<S> {
+	/// The status the request was answered with.
+	pub status: StatusCode,
+	/// The request, if it could be parsed.
+	pub request: Option<Request>,
+	/// The stream the answer was written to, `None` if there is no
+	/// connection to answer on.
+	pub stream: Option<S>,
+	/// The error writing the answer, if there was one.
+	pub error: Option<IoError>,
+}
+
+impl<S> fmt::Debug for Unrouted<S> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.debug_struct("Unrouted")
+		   .field("status", &self.status)
+		   .field("request", &self.request)
+		   .field("error", &self.error)
+		   .finish()
+	}
+}
+
+impl<S, T> Router<S, T>
+    where S: Stream
+{
+	/// A router without routes, answering every request with 404.
+	pub fn new() -> Self {
+		Router { routes: Vec::new() }
+	}
+
+	/// Adds a route for requests for any host.
+	///
+	/// Panics if the pattern doesn't start with `/`, has an unclosed `{` or
+	/// has a `{*rest}` segment that isn't the last.
+	pub fn route<F>(self, pattern: &str, handler: F) -> Self
+		where F: Fn(WsUpgrade<S>, Params) -> T + Send + Sync + 'static
+	{
+		self.add(None, pattern, handler)
+	}
+
+	/// Adds a route for requests for a host, like `example.com` or
+	/// `*.example.com`. Panics on the same patterns `route` does.
+	pub fn route_host<F>(self, host: &str, pattern: &str, handler: F) -> Self
+		where F: Fn(WsUpgrade<S>, Params) -> T + Send + Sync + 'static
+	{
+		self.add(Some(host.to_lowercase()), pattern, handler)
+	}
+
+	fn add<F>(mut self, host: Option<String>, pattern: &str, handler: F) -> Self
+		where F: Fn(WsUpgrade<S>, Params) -> T + Send + Sync + 'static
+	{
+		self.routes.push(Route {
+		                     host: host,
+		                     segments: parse_pattern(pattern),
+		                     handler: Box::new(handler),
+		                 });
+		self
+	}
+
+	/// Hands the upgrade to the handler of the first route it matches.
+	/// If there is none, the request is answered with 404 Not Found.
+	pub fn dispatch(&self, upgrade: WsUpgrade<S>) -> Result<T, Unrouted<S>> {
+		let found = {
+			let host = request_host(&upgrade.request);
+			let path = upgrade.request.subject.1.path();
+			self.routes
+			    .iter()
+			    .filter_map(|route| route.matches(host.as_ref().map(|host| &host[..]), path).map(|params| (route, params)))
+			    .next()
+		};
+		if let Some((route, params)) = found {
+			return Ok((route.handler)(upgrade, params));
+		}
+
+		let request = upgrade.request.clone();
+		let (stream, error) = match upgrade.reject_with_status(StatusCode::NotFound, &Headers::new()) {
+			Ok(stream) => (stream, None),
+			Err((stream, error)) => (stream, Some(error)),
+		};
+		Err(Unrouted {
+		        status: StatusCode::NotFound,
+		        request: Some(request),
+		        stream: Some(stream),
+		        error: error,
+		    })
+	}
+
+	/// Answers a connection that isn't a valid upgrade request: with 404 Not
+	/// Found if no route matches it, otherwise with 426 Upgrade Required if
+	/// it doesn't ask for a WebSocket 13 upgrade, like a plain HTTP request,
+	/// or with 400 Bad Request if it's a broken upgrade request.
+	pub fn reject_invalid(&self, connection: InvalidConnection<S>) -> Unrouted<S> {
+		let InvalidConnection { stream, parsed, error, .. } = connection;
+		let routed = parsed.as_ref().map_or(false, |request| {
+			let host = request_host(request);
+			let path = request.subject.1.path();
+			self.routes
+			    .iter()
+			    .any(|route| route.matches(host.as_ref().map(|host| &host[..]), path).is_some())
+		});
+
+		let upgrading = parsed.as_ref().map_or(false, |request| match request.headers.get() {
+			Some(&Upgrade(ref upgrade)) => upgrade.iter().any(|u| u.name == ProtocolName::WebSocket),
+			None => false,
+		});
+		let mut headers = Headers::new();
+		let status = match error {
+			_ if parsed.is_some() && !routed => StatusCode::NotFound,
+			HyperIntoWsError::UnsupportedWebsocketVersion => StatusCode::UpgradeRequired,
+			_ if parsed.is_some() && !upgrading => StatusCode::UpgradeRequired,
+			_ => StatusCode::BadRequest,
+		};
+		if status == StatusCode::UpgradeRequired {
+			headers.set(WebSocketVersion::WebSocket13);
+			headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+			headers.set(Connection(vec![
+				ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_string()))
+			]));
+		}
+
+		let mut error = None;
+		let stream = stream.map(|mut stream| {
+			let version = parsed.as_ref().map_or(HttpVersion::Http11, |request| request.version);
+			if let Err(e) = write!(&mut stream, "{} {}\r\n{}\r\n", version, status, headers) {
+				error = Some(e);
+			}
+			stream
+		});
+		Unrouted {
+			status: status,
+			request: parsed,
+			stream: stream,
+			error: error,
+		}
+	}
+}
+
+impl<S, T> Default for Router<S, T>
+    where S: Stream
+{
+	fn default() -> Self {
+		Router::new()
+	}
+}
+
+impl<S, T> Route<S, T>
+    where S: Stream
+{
+	fn matches(&self, host: Option<&str>, path: &str) -> Option<Params> {
+		if let Some(ref pattern) = self.host {
+			let matched = host.map_or(false, |host| if pattern.starts_with("*.") {
+				host.len() > pattern.len() - 1 && host.ends_with(&pattern[1..])
+			} else {
+				host == pattern
+			});
+			if !matched {
+				return None;
+			}
+		}
+
+		if !path.starts_with('/') {
+			return None;
+		}
+		let mut segments = path[1..].split('/');
+		let mut params = Vec::new();
+		for segment in &self.segments {
+			match *segment {
+				Segment::Literal(ref literal) => {
+					if segments.next() != Some(literal) {
+						return None;
+					}
+				}
+				Segment::Param(ref name) => {
+					match segments.next() {
+						Some(value) if !value.is_empty() => params.push((name.clone(), decode(value))),
+						_ => return None,
+					}
+				}
+				Segment::Rest(ref name) => {
+					let rest: Vec<&str> = segments.by_ref().collect();
+					params.push((name.clone(), decode(&rest.join("/"))));
+				}
+			}
+		}
+		if segments.next().is_some() {
+			return None;
+		}
+		Some(Params { params: params })
+	}
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+	assert!(pattern.starts_with('/'),
+	        "route pattern {:?} doesn't start with /",
+	        pattern);
+	let segments: Vec<Segment> = pattern[1..]
+		.split('/')
+		.map(|segment| if segment.starts_with("{*") && segment.ends_with('}') {
+		         Segment::Rest(segment[2..segment.len() - 1].to_owned())
+		     } else if segment.starts_with('{') && segment.ends_with('}') {
+		         Segment::Param(segment[1..segment.len() - 1].to_owned())
+		     } else {
+		         assert!(!segment.contains('{') && !segment.contains('}'),
+		                 "route pattern {:?} has a malformed segment",
+		                 pattern);
+		         Segment::Literal(segment.to_owned())
+		     })
+		.collect();
+	let rest = segments.iter().position(|segment| match *segment {
+		                                    Segment::Rest(_) => true,
+		                                    _ => false,
+		                                   });
+	assert!(rest.map_or(true, |rest| rest == segments.len() - 1),
+	        "route pattern {:?} has segments after {{*rest}}",
+	        pattern);
+	segments
+}
+
+/// The host the request is for, lowercase and without the port.
+fn request_host(request: &Request) -> Option<String> {
+	request.headers
+	       .get::<Host>()
+	       .map(|host| host.hostname.to_lowercase())
+	       .or_else(|| match request.subject.1 {
+		                ::handshake::RequestUri::AbsoluteUri(ref url) => url.host_str().map(str::to_lowercase),
+		                _ => None,
+		               })
+}
+
+fn decode(segment: &str) -> String {
+	percent_decode(segment.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Read};
+	use stream::ReadWritePair;
+	use server::upgrade::IntoWs;
+
+	type Pair = ReadWritePair<Cursor<Vec<u8>>, Vec<u8>>;
+
+	fn upgrade(host: &str, target: &str) -> WsUpgrade<Pair> {
+		let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\n\
+		                       Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+		                       Sec-WebSocket-Version: 13\r\n\r\n",
+		                      target,
+		                      host);
+		ReadWritePair(Cursor::new(request.into_bytes()), Vec::new()).into_ws().ok().unwrap()
+	}
+
+	fn router() -> Router<Pair, String> {
+		Router::new()
+			.route_host("*.example.com", "/admin", |_, _| "admin".to_owned())
+			.route("/chat", |_, _| "chat".to_owned())
+			.route("/feed/{symbol}", |_, params| format!("feed {}", params.get("symbol").unwrap()))
+			.route("/files/{*path}", |_, params| format!("files {:?}", params.get("path").unwrap()))
+	}
+
+	#[test]
+	fn dispatches_by_host_and_path() {
+		let router = router();
+		let route = |host, target| router.dispatch(upgrade(host, target)).ok();
+		assert_eq!(route("example.com", "/chat?room=1"), Some("chat".to_owned()));
+		assert_eq!(route("example.com", "/feed/BRK%2EA"), Some("feed BRK.A".to_owned()));
+		assert_eq!(route("example.com", "/files/a/b"), Some("files \"a/b\"".to_owned()));
+		assert_eq!(route("example.com", "/files/"), Some("files \"\"".to_owned()));
+		assert_eq!(route("Ops.Example.com:8080", "/admin"), Some("admin".to_owned()));
+		assert_eq!(route("example.com", "/admin"), None);
+		assert_eq!(route("example.com", "/feed/"), None);
+		assert_eq!(route("example.com", "/chat/extra"), None);
+
+		let unrouted = router.dispatch(upgrade("example.com", "/nowhere")).err().unwrap();
+		assert_eq!(unrouted.status, StatusCode::NotFound);
+		let written = unrouted.stream.unwrap().1;
+		assert!(String::from_utf8(written).unwrap().starts_with("HTTP/1.1 404 Not Found\r\n"));
+	}
+
+	#[test]
+	fn rejects_invalid_connections() {
+		let router = router();
+		let reject = |target: &str, headers: &str| {
+			let request = format!("GET {} HTTP/1.1\r\nHost: example.com\r\n{}\r\n", target, headers);
+			let (stream, parsed, _, error) = ReadWritePair(Cursor::new(request.into_bytes()), Vec::new())
+				.into_ws()
+				.err()
+				.unwrap();
+			let unrouted = router.reject_invalid(InvalidConnection {
+			                                         stream: Some(stream),
+			                                         parsed: parsed,
+			                                         buffer: None,
+			                                         error: error,
+			                                     });
+			let mut written = String::new();
+			Cursor::new(unrouted.stream.unwrap().1).read_to_string(&mut written).unwrap();
+			(unrouted.status, written)
+		};
+
+		let (status, written) = reject("/chat", "");
+		assert_eq!(status, StatusCode::UpgradeRequired);
+		assert!(written.contains("Sec-WebSocket-Version: 13\r\n"));
+		assert_eq!(reject("/chat", "Upgrade: websocket\r\nConnection: Upgrade\r\n").0,
+		           StatusCode::BadRequest);
+		assert_eq!(reject("/index.html", "").0, StatusCode::NotFound);
+	}
+
+	#[test]
+	#[should_panic]
+	fn rest_must_be_last() {
+		parse_pattern("/files/{*path}/raw");
+	}
+}
@@ -0,0 +1,517 @@
+//! TLS for servers answering for several hostnames, with certificates that
+//! can be replaced while running. This module is only available with the
+//! `ssl` feature.
+//!
+//! An `SniAcceptor` picks the certificate for each connection from the name
+//! the client asked for (Server Name Indication) and hands out a normal
+//! `SslAcceptor` for `Server::bind_secure`:
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::Server;
+//! use websocket::server::tls::{SniAcceptorBuilder, ClientAuth};
+//!
+//! let sni = SniAcceptorBuilder::new("default.pem", "default.key")
+//!     .certificate("api.example.com", "api.pem", "api.key")
+//!     .certificate("*.example.com", "wildcard.pem", "wildcard.key")
+//!     .verify_clients(ClientAuth::Optional, "clients-ca.pem")
+//!     .build()
+//!     .unwrap();
+//!
+//! let server = Server::bind_secure("0.0.0.0:443", sni.acceptor()).unwrap();
+//!
+//! // certificates were renewed on disk, new connections get the new ones
+//! // and connections already made keep going
+//! sni.reload().unwrap();
+//! # }
+//! ```
+//!
+//! Servers using rustls are out of scope here: rustls picks certificates by
+//! name with `rustls::server::ResolvesServerCertUsingSni`, or any
+//! `ResolvesServerCert` of one's own, set on the `ServerConfig` given to
+//! `Server::bind_rustls`.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use openssl::dh::Dh;
+use openssl::ec::EcKey;
+use openssl::error::ErrorStack;
+use openssl::nid;
+use openssl::sha::sha256;
+use openssl::ssl::{self, SslMethod, SslContext, SslContextBuilder, SslAcceptor, SslAcceptorBuilder,
+                   SslRef, SniError, SSL_VERIFY_PEER, SSL_VERIFY_FAIL_IF_NO_PEER_CERT};
+use openssl::version;
+use openssl::x509::{X509Ref, X509Name, X509_FILETYPE_PEM};
+
+/// The ffdhe2048 group, which Mozilla's intermediate configuration uses.
+const DH_PARAMS: &'static str = "-----BEGIN DH PARAMETERS-----
+MIIBCAKCAQEA//////////+t+FRYortKmq/cViAnPTzx2LnFg84tNpWp4TZBFGQz
++8yTnc4kmz75fS/jY2MMddj2gbICrsRhetPfHtXV/WVhJDP1H18GbtCFY2VVPe0a
+87VXE15/V8k1mE8McODmi3fipona8+/och3xWKE2rec1MKzKT0g6eXq8CrGCsyT7
+YdEIqUuyyOP7uWrat2DX9GgdT0Kj3jlN9K5W7edjcrsZCwenyO4KbXCeAvzhzffi
+7MA0BM0oNC9hkXL+nOmFg/+OTxIy7vKBg8P+OxtMb61zO7X8vC7CIAXFjvGDfRaD
+ssbzSibBsu/6iGtCOGEoXJf//////////wIBAg==
+-----END DH PARAMETERS-----
+";
+
+/// The cipher suites of Mozilla's intermediate configuration.
+const CIPHERS: &'static str = "ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-CHACHA20-POLY1305:\
+                               ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+                               ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:\
+                               DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:\
+                               ECDHE-ECDSA-AES128-SHA256:ECDHE-RSA-AES128-SHA256:ECDHE-ECDSA-AES128-SHA:\
+                               ECDHE-RSA-AES256-SHA384:ECDHE-RSA-AES128-SHA:ECDHE-ECDSA-AES256-SHA384:\
+                               ECDHE-ECDSA-AES256-SHA:ECDHE-RSA-AES256-SHA:DHE-RSA-AES128-SHA256:\
+                               DHE-RSA-AES128-SHA:DHE-RSA-AES256-SHA256:DHE-RSA-AES256-SHA:\
+                               ECDHE-ECDSA-DES-CBC3-SHA:ECDHE-RSA-DES-CBC3-SHA:EDH-RSA-DES-CBC3-SHA:\
+                               AES128-GCM-SHA256:AES256-GCM-SHA384:AES128-SHA256:AES256-SHA256:\
+                               AES128-SHA:AES256-SHA:DES-CBC3-SHA:!DSS";
+
+/// Whether clients have to present a certificate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+	/// Clients are asked for a certificate but may connect without one,
+	/// a certificate they do present still has to be valid.
+	Optional,
+	/// Clients without a valid certificate can't connect.
+	Required,
+}
+
+/// Builds an `SniAcceptor`, certificate chains and keys are PEM files which
+/// are read by `build` and again on every `SniAcceptor::reload`.
+#[derive(Clone, Debug)]
+pub struct SniAcceptorBuilder {
+	default: (PathBuf, PathBuf),
+	names: Vec<(String, PathBuf, PathBuf)>,
+	client_auth: Option<(ClientAuth, PathBuf)>,
+}
+
+impl SniAcceptorBuilder {
+	/// Starts with the certificate chain and private key used when the
+	/// client sends no name or one without a certificate of its own.
+	pub fn new<C, K>(chain: C, key: K) -> Self
+		where C: AsRef<Path>,
+		      K: AsRef<Path>
+	{
+		SniAcceptorBuilder {
+			default: (chain.as_ref().to_path_buf(), key.as_ref().to_path_buf()),
+			names: Vec::new(),
+			client_auth: None,
+		}
+	}
+
+	/// Uses this certificate chain and private key for connections to
+	/// `name`. A name like `*.example.com` covers any one label in front of
+	/// `example.com`, an exact name wins over a wildcard.
+	pub fn certificate<C, K>(mut self, name: &str, chain: C, key: K) -> Self
+		where C: AsRef<Path>,
+		      K: AsRef<Path>
+	{
+		self.names
+		    .push((name.to_lowercase(), chain.as_ref().to_path_buf(), key.as_ref().to_path_buf()));
+		self
+	}
+
+	/// Asks clients for a certificate signed by one of the CA certificates
+	/// in the PEM file `ca`. The certificate a client presented can be read
+	/// with `WsUpgrade::client_certificate`.
+	pub fn verify_clients<P>(mut self, auth: ClientAuth, ca: P) -> Self
+		where P: AsRef<Path>
+	{
+		self.client_auth = Some((auth, ca.as_ref().to_path_buf()));
+		self
+	}
+
+	/// Loads all certificates and builds the acceptor.
+	pub fn build(self) -> Result<SniAcceptor, ErrorStack> {
+		let settings = Arc::new(self);
+		let contexts = Arc::new(RwLock::new(try!(Contexts::load(&settings))));
+
+		let mut base = try!(SslAcceptorBuilder::mozilla_intermediate_raw(SslMethod::tls()));
+		try!(settings.configure(&mut base, &settings.default));
+		let selected = contexts.clone();
+		// OpenSSL calls this for every handshake, with or without a name,
+		// so the default certificate is swapped in by `reload` as well
+		base.set_servername_callback(move |ssl: &mut SslRef| {
+			let contexts = selected.read().unwrap_or_else(|e| e.into_inner());
+			let context = contexts.select(ssl.servername());
+			// 80 is the internal_error alert
+			ssl.set_ssl_context(context).map_err(|_| SniError::Fatal(80))
+		});
+
+		Ok(SniAcceptor {
+		       settings: settings,
+		       contexts: contexts,
+		       acceptor: base.build(),
+		   })
+	}
+
+	fn configure(
+		&self,
+		builder: &mut SslContextBuilder,
+		&(ref chain, ref key): &(PathBuf, PathBuf),
+	) -> Result<(), ErrorStack> {
+		try!(builder.set_certificate_chain_file(chain));
+		try!(builder.set_private_key_file(key, X509_FILETYPE_PEM));
+		try!(builder.check_private_key());
+		if let Some((auth, ref ca)) = self.client_auth {
+			// the verify mode is kept from the first context, but the
+			// trusted certificates come from the selected one
+			try!(builder.set_ca_file(ca));
+			builder.set_client_ca_list(try!(X509Name::load_client_ca_file(ca)));
+			try!(builder.set_session_id_context(b"websocket"));
+			builder.set_verify(match auth {
+				ClientAuth::Optional => SSL_VERIFY_PEER,
+				ClientAuth::Required => SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+			});
+		}
+		Ok(())
+	}
+}
+
+/// Accepts TLS connections with a certificate picked by the name the
+/// client asked for. Clones share their certificates, so one clone can
+/// `reload` them while another is used by a server.
+#[derive(Clone)]
+pub struct SniAcceptor {
+	settings: Arc<SniAcceptorBuilder>,
+	contexts: Arc<RwLock<Contexts>>,
+	acceptor: SslAcceptor,
+}
+
+impl SniAcceptor {
+	/// The acceptor to give to `Server::bind_secure`, it keeps following
+	/// the certificates of this `SniAcceptor` after a `reload`.
+	pub fn acceptor(&self) -> SslAcceptor {
+		self.acceptor.clone()
+	}
+
+	/// Reads all certificates and keys from disk again. Handshakes after
+	/// this use the new ones, connections already made are left alone.
+	/// If a file can't be loaded the certificates in use are kept.
+	pub fn reload(&self) -> Result<(), ErrorStack> {
+		let contexts = try!(Contexts::load(&self.settings));
+		*self.contexts.write().unwrap_or_else(|e| e.into_inner()) = contexts;
+		Ok(())
+	}
+}
+
+impl fmt::Debug for SniAcceptor {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		fmt.debug_struct("SniAcceptor")
+		   .field("settings", &self.settings)
+		   .finish()
+	}
+}
+
+struct Contexts {
+	default: SslContext,
+	names: HashMap<String, SslContext>,
+}
+
+impl Contexts {
+	fn load(settings: &SniAcceptorBuilder) -> Result<Contexts, ErrorStack> {
+		let mut names = HashMap::new();
+		for &(ref name, ref chain, ref key) in &settings.names {
+			let paths = (chain.clone(), key.clone());
+			names.insert(name.clone(), try!(Contexts::context(settings, &paths)));
+		}
+		Ok(Contexts {
+		       default: try!(Contexts::context(settings, &settings.default)),
+		       names: names,
+		   })
+	}
+
+	fn context(
+		settings: &SniAcceptorBuilder,
+		paths: &(PathBuf, PathBuf),
+	) -> Result<SslContext, ErrorStack> {
+		let mut builder = try!(intermediate());
+		try!(settings.configure(&mut builder, paths));
+		Ok(builder.build())
+	}
+
+	fn select(&self, name: Option<&str>) -> &SslContext {
+		let name = match name {
+			Some(name) => name.to_lowercase(),
+			None => return &self.default,
+		};
+		if let Some(context) = self.names.get(&name) {
+			return context;
+		}
+		let wildcard = match name.find('.') {
+			Some(dot) => format!("*{}", &name[dot..]),
+			None => return &self.default,
+		};
+		self.names.get(&wildcard).unwrap_or(&self.default)
+	}
+}
+
+/// A context set up like `SslAcceptorBuilder::mozilla_intermediate_raw`
+/// does, which only hands out an `SslAcceptor`. The handshake goes on with
+/// the context picked by name, so it needs the same protocol versions,
+/// options and ciphers as the first one.
+fn intermediate() -> Result<SslContextBuilder, ErrorStack> {
+	let mut builder = try!(SslContext::builder(SslMethod::tls()));
+	let options = (ssl::SSL_OP_ALL & !ssl::SSL_OP_NETSCAPE_REUSE_CIPHER_CHANGE_BUG &
+	               !ssl::SSL_OP_DONT_INSERT_EMPTY_FRAGMENTS) | ssl::SSL_OP_NO_TICKET |
+	              ssl::SSL_OP_NO_COMPRESSION | ssl::SSL_OP_NO_SSLV2 | ssl::SSL_OP_NO_SSLV3 |
+	              ssl::SSL_OP_SINGLE_DH_USE | ssl::SSL_OP_SINGLE_ECDH_USE |
+	              ssl::SSL_OP_CIPHER_SERVER_PREFERENCE;
+	builder.set_options(options);
+	let mut mode = ssl::SSL_MODE_AUTO_RETRY | ssl::SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER |
+	               ssl::SSL_MODE_ENABLE_PARTIAL_WRITE;
+	// leaks memory before OpenSSL 1.0.1h
+	if version::number() >= 0x1000108f {
+		mode |= ssl::SSL_MODE_RELEASE_BUFFERS;
+	}
+	builder.set_mode(mode);
+	let dh = try!(Dh::from_pem(DH_PARAMS.as_bytes()));
+	try!(builder.set_tmp_dh(&dh));
+	// OpenSSL 1.1.0 and later pick the curve themselves
+	if version::number() < 0x10100000 {
+		let curve = try!(EcKey::from_curve_name(nid::X9_62_PRIME256V1));
+		try!(builder.set_tmp_ecdh(&curve));
+	}
+	try!(builder.set_cipher_list(CIPHERS));
+	Ok(builder)
+}
+
+/// A certificate a client presented and the server verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCertificate {
+	/// The subject's distinguished name, like `C=US, O=Example, CN=alice`.
+	pub subject: String,
+	/// The subject's common name, if it has one.
+	pub common_name: Option<String>,
+	/// The DNS names and IP addresses in the subject alternative names.
+	pub subject_alt_names: Vec<String>,
+	/// The SHA-256 hash of the DER encoded certificate.
+	pub fingerprint: [u8; 32],
+	/// The DER encoded certificate.
+	pub der: Vec<u8>,
+}
+
+impl ClientCertificate {
+	/// Reads the interesting parts of a certificate.
+	pub fn from_x509(certificate: &X509Ref) -> Result<ClientCertificate, ErrorStack> {
+		let name = certificate.subject_name();
+		let mut subject = Vec::new();
+		let mut common_name = None;
+		for &(nid, label) in &[(nid::COUNTRYNAME, "C"),
+		                       (nid::STATEORPROVINCENAME, "ST"),
+		                       (nid::LOCALITYNAME, "L"),
+		                       (nid::ORGANIZATIONNAME, "O"),
+		                       (nid::ORGANIZATIONALUNITNAME, "OU"),
+		                       (nid::COMMONNAME, "CN")] {
+			for entry in name.entries_by_nid(nid) {
+				let value = try!(entry.data().as_utf8()).to_string();
+				if label == "CN" && common_name.is_none() {
+					common_name = Some(value.clone());
+				}
+				subject.push(format!("{}={}", label, value));
+			}
+		}
+
+		let mut subject_alt_names = Vec::new();
+		if let Some(names) = certificate.subject_alt_names() {
+			for name in &names {
+				if let Some(dns) = name.dnsname() {
+					subject_alt_names.push(dns.to_owned());
+				} else if let Some(ip) = name.ipaddress().and_then(ip_address) {
+					subject_alt_names.push(ip.to_string());
+				}
+			}
+		}
+
+		let der = try!(certificate.to_der());
+		Ok(ClientCertificate {
+		       subject: subject.join(", "),
+		       common_name: common_name,
+		       subject_alt_names: subject_alt_names,
+		       fingerprint: sha256(&der),
+		       der: der,
+		   })
+	}
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+	match bytes.len() {
+		4 => {
+			let mut octets = [0; 4];
+			octets.copy_from_slice(bytes);
+			Some(IpAddr::from(octets))
+		}
+		16 => {
+			let mut octets = [0; 16];
+			octets.copy_from_slice(bytes);
+			Some(IpAddr::from(octets))
+		}
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::io::{Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::process;
+	use std::thread;
+	use openssl::ssl::{SslConnectorBuilder, SslStream, SSL_VERIFY_NONE};
+	use rcgen::{self, CertificateParams, DnType, IsCa, BasicConstraints, KeyPair};
+	use client::ClientBuilder;
+	use server::Server;
+
+	struct Issued {
+		cert: rcgen::Certificate,
+		key: KeyPair,
+	}
+
+	/// Writes `<file>.pem` and `<file>.key` for a certificate named `name`,
+	/// a CA certificate if there is no issuer.
+	fn issue(dir: &Path, file: &str, name: &str, issuer: Option<&Issued>) -> Issued {
+		let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+		params.distinguished_name.push(DnType::CommonName, name);
+		let key = KeyPair::generate().unwrap();
+		let cert = match issuer {
+			Some(issuer) => params.signed_by(&key, &issuer.cert, &issuer.key).unwrap(),
+			None => {
+				params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+				params.self_signed(&key).unwrap()
+			}
+		};
+		fs::write(dir.join(format!("{}.pem", file)), cert.pem()).unwrap();
+		fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+		Issued {
+			cert: cert,
+			key: key,
+		}
+	}
+
+	fn temp_dir(test: &str) -> PathBuf {
+		let dir = env::temp_dir().join(format!("websocket-{}-{}", test, process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	/// Makes a TLS connection asking for `name`, returning both ends and
+	/// the common name of the certificate the server used.
+	fn handshake(
+		acceptor: &SslAcceptor,
+		name: &'static str,
+	) -> (SslStream<TcpStream>, SslStream<TcpStream>, String) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let client = thread::spawn(move || {
+			let mut connector = SslConnectorBuilder::new(SslMethod::tls()).unwrap();
+			connector.set_verify(SSL_VERIFY_NONE);
+			let tcp = TcpStream::connect(addr).unwrap();
+			connector.build().connect(name, tcp).unwrap()
+		});
+		let server = acceptor.accept(listener.accept().unwrap().0).unwrap();
+		let client = client.join().unwrap();
+		let cert = client.ssl().peer_certificate().unwrap();
+		let common_name = ClientCertificate::from_x509(&cert).unwrap().common_name.unwrap();
+		(server, client, common_name)
+	}
+
+	#[test]
+	fn sni_selects_and_reloads_certificates() {
+		let dir = temp_dir("sni");
+		let ca = issue(&dir, "ca", "ca", None);
+		issue(&dir, "default", "default.test", Some(&ca));
+		issue(&dir, "api", "api.test", Some(&ca));
+		issue(&dir, "wildcard", "*.apps.test", Some(&ca));
+
+		let sni = SniAcceptorBuilder::new(dir.join("default.pem"), dir.join("default.key"))
+			.certificate("API.test", dir.join("api.pem"), dir.join("api.key"))
+			.certificate("*.apps.test", dir.join("wildcard.pem"), dir.join("wildcard.key"))
+			.build()
+			.unwrap();
+		let acceptor = sni.acceptor();
+
+		assert_eq!(handshake(&acceptor, "api.test").2, "api.test");
+		assert_eq!(handshake(&acceptor, "chat.apps.test").2, "*.apps.test");
+		assert_eq!(handshake(&acceptor, "other.test").2, "default.test");
+		let (mut server, mut client, _) = handshake(&acceptor, "api.test");
+
+		issue(&dir, "api", "api-renewed.test", Some(&ca));
+		sni.reload().unwrap();
+		assert_eq!(handshake(&acceptor, "api.test").2, "api-renewed.test");
+
+		// a connection made before the reload keeps working
+		client.write_all(b"still here").unwrap();
+		let mut buf = [0; 10];
+		server.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"still here");
+
+		// a broken file doesn't take down the certificates in use
+		fs::write(dir.join("api.key"), "not a key").unwrap();
+		assert!(sni.reload().is_err());
+		assert_eq!(handshake(&acceptor, "api.test").2, "api-renewed.test");
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn named_contexts_are_hardened() {
+		let options = intermediate().unwrap().options();
+		for option in &[ssl::SSL_OP_NO_SSLV3, ssl::SSL_OP_NO_COMPRESSION, ssl::SSL_OP_NO_TICKET,
+		                ssl::SSL_OP_CIPHER_SERVER_PREFERENCE] {
+			assert!(options.contains(*option));
+		}
+	}
+
+	#[test]
+	fn client_certificate_on_upgrade() {
+		let dir = temp_dir("mtls");
+		let ca = issue(&dir, "ca", "ca", None);
+		issue(&dir, "server", "localhost", Some(&ca));
+		let alice = issue(&dir, "alice", "alice.test", Some(&ca));
+
+		let sni = SniAcceptorBuilder::new(dir.join("server.pem"), dir.join("server.key"))
+			.verify_clients(ClientAuth::Required, dir.join("ca.pem"))
+			.build()
+			.unwrap();
+		let mut server = Server::bind_secure("127.0.0.1:0", sni.acceptor()).unwrap();
+		let url = format!("wss://localhost:{}", server.local_addr().unwrap().port());
+
+		let client_dir = dir.clone();
+		let remote = thread::spawn(move || {
+			let with_cert = ClientBuilder::new(&url)
+				.unwrap()
+				.add_root_certificate_pem(client_dir.join("ca.pem"))
+				.client_certificate_pem(client_dir.join("alice.pem"), client_dir.join("alice.key"))
+				.connect_secure(None)
+				.is_ok();
+			let without_cert = ClientBuilder::new(&url)
+				.unwrap()
+				.add_root_certificate_pem(client_dir.join("ca.pem"))
+				.connect_secure(None)
+				.is_err();
+			(with_cert, without_cert)
+		});
+
+		let upgrade = server.accept().ok().unwrap();
+		let cert = upgrade.client_certificate().unwrap();
+		assert_eq!(cert.subject, "CN=alice.test");
+		assert_eq!(cert.common_name, Some("alice.test".to_string()));
+		assert_eq!(cert.subject_alt_names, vec!["alice.test".to_string()]);
+		assert_eq!(cert.der, alice.cert.der().to_vec());
+		assert_eq!(cert.fingerprint, sha256(&cert.der));
+		upgrade.accept().ok().unwrap();
+
+		// required means no certificate, no connection
+		assert!(server.accept().is_err());
+		assert_eq!(remote.join().unwrap(), (true, true));
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
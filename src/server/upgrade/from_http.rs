@@ -0,0 +1,134 @@
+//! Upgrade a request from the `http` crate to a websocket connection.
+//! This module is only available with the `http` feature.
+//!
+//! Frameworks built on the `http` crate usually parse the request themselves
+//! and hand over an `http::Request<()>` along with the raw stream. Pairing the
+//! two up in an `HttpRequest` is enough to start a websocket handshake:
+//!
+//! ```rust,no_run
+//! # extern crate http;
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::net::TcpStream;
+//! use websocket::Message;
+//! use websocket::server::upgrade::IntoWs;
+//! use websocket::server::upgrade::from_http::HttpRequest;
+//!
+//! # fn framework_request() -> (http::Request<()>, TcpStream) { unimplemented!() }
+//! let (request, stream) = framework_request();
+//!
+//! match HttpRequest(request, stream).into_ws() {
+//!     Ok(upgrade) => {
+//!         let mut client = upgrade.accept().unwrap();
+//!         client.send_message(&Message::text("hello from http")).unwrap();
+//!     }
+//!     Err((request, stream, err)) => {
+//!         // not a websocket request, handle it as plain HTTP
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! If the framework writes the response itself, use `WsUpgrade::accept_http`
+//! to get the handshake response as an `http::Response<()>` instead.
+
+use http;
+use super::{IntoWs, WsUpgrade};
+use handshake::Incoming;
+use server::request::TrustedProxies;
+use header::Headers;
+use stream::Stream;
+
+use super::validate;
+use super::HyperIntoWsError;
+
+/// An `http::Request` paired with the stream it was read from.
+/// A tuple can't be used directly since every `Stream` is already `IntoWs`,
+/// so like `HyperRequest` this struct tells the two apart.
+pub struct HttpRequest<S: Stream>(pub http::Request<()>, pub S);
+
+impl<S> From<(http::Request<()>, S)> for HttpRequest<S>
+    where S: Stream
+{
+	fn from(pair: (http::Request<()>, S)) -> HttpRequest<S> {
+		HttpRequest(pair.0, pair.1)
+	}
+}
+
+impl<S> IntoWs for HttpRequest<S>
+    where S: Stream
+{
+	type Stream = S;
+	type Error = (http::Request<()>, S, HyperIntoWsError);
+
+	fn into_ws(self) -> Result<WsUpgrade<Self::Stream>, Self::Error> {
+		let HttpRequest(request, stream) = self;
+		let method = request.method().into();
+		let version = request.version().into();
+		let headers = Headers::from(request.headers());
+
+		if let Err(e) = validate(&method, &version, &headers) {
+			return Err((request, stream, e));
+		}
+
+		Ok(WsUpgrade {
+		       headers: Headers::new(),
+		       stream: stream,
+		       buffer: None,
+		       request: Incoming {
+		           version: version,
+		           headers: headers,
+		           subject: (method, request.uri().into()),
+		       },
+		       trusted_proxies: TrustedProxies::None,
+		   })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use stream::ReadWritePair;
+	use header::WebSocketAccept;
+
+	fn handshake_request() -> http::Request<()> {
+		http::Request::get("/chat?room=1")
+			.header("Host", "example.com")
+			.header("Upgrade", "websocket")
+			.header("Connection", "Upgrade")
+			.header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+			.header("Sec-WebSocket-Version", "13")
+			.body(())
+			.unwrap()
+	}
+
+	#[test]
+	fn upgrade_http_request() {
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		let upgrade = HttpRequest::from((handshake_request(), stream)).into_ws().ok().unwrap();
+		assert_eq!(upgrade.request.subject.1.to_string(), "/chat?room=1");
+
+		let (response, client) = upgrade.accept_http();
+		assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+		assert_eq!(response.headers()["sec-websocket-accept"],
+		           "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+		assert!(client.headers().has::<WebSocketAccept>());
+
+		// nothing was written, that is up to the caller
+		let (stream, _) = client.into_stream();
+		assert!((stream.1).get_ref().is_empty());
+	}
+
+	#[test]
+	fn reject_plain_http_request() {
+		let request = http::Request::get("/").body(()).unwrap();
+		let stream = ReadWritePair(Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+		match HttpRequest(request, stream).into_ws() {
+			Err((request, _, HyperIntoWsError::NoSecWsKeyHeader)) => {
+				assert_eq!(request.uri(), "/");
+			}
+			_ => panic!("expected the request to be handed back"),
+		}
+	}
+}
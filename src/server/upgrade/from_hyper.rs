@@ -1,4 +1,5 @@
 //! Upgrade a hyper connection to a websocket one.
+//! This module is only available with the `hyper` feature.
 //!
 //! Using this method, one can start a hyper server and check if each request
 //! is a websocket upgrade request, if so you can use websockets and hyper on the
@@ -36,15 +37,11 @@
 //! ```
 
 use hyper::net::NetworkStream;
-use super::{IntoWs, WsUpgrade, Buffer};
-
-pub use hyper::http::h1::Incoming;
-pub use hyper::method::Method;
-pub use hyper::version::HttpVersion;
-pub use hyper::uri::RequestUri;
-pub use hyper::buffer::BufReader;
 use hyper::server::Request;
-pub use hyper::header::{Headers, Upgrade, ProtocolName, Connection, ConnectionOption};
+use super::{IntoWs, WsUpgrade, Buffer};
+use handshake::Incoming;
+use server::request::TrustedProxies;
+use header::Headers;
 
 use super::validate;
 use super::HyperIntoWsError;
@@ -59,12 +56,15 @@ impl<'a, 'b> IntoWs for HyperRequest<'a, 'b> {
 	type Error = (Request<'a, 'b>, HyperIntoWsError);
 
 	fn into_ws(self) -> Result<WsUpgrade<Self::Stream>, Self::Error> {
-		if let Err(e) = validate(&self.0.method, &self.0.version, &self.0.headers) {
+		let method = self.0.method.clone().into();
+		let version = self.0.version.into();
+		let headers = Headers::from(&self.0.headers);
+
+		if let Err(e) = validate(&method, &version, &headers) {
 			return Err((self.0, e));
 		}
 
-		let (_, method, headers, uri, version, reader) =
-			self.0.deconstruct();
+		let (_, _, _, uri, _, reader) = self.0.deconstruct();
 
 		let reader = reader.into_inner();
 		let (buf, pos, cap) = reader.take_buf();
@@ -81,8 +81,9 @@ impl<'a, 'b> IntoWs for HyperRequest<'a, 'b> {
 		       request: Incoming {
 		           version: version,
 		           headers: headers,
-		           subject: (method, uri),
+		           subject: (method, uri.into()),
 		       },
+		       trusted_proxies: TrustedProxies::None,
 		   })
 	}
 }
@@ -11,18 +11,21 @@ use header::extensions::Extension;
 use header::{WebSocketAccept, WebSocketKey, WebSocketVersion, WebSocketProtocol,
              WebSocketExtensions, Origin};
 use client::Client;
+use server::request::{UpgradeRequest, TrustedProxies};
 
 use unicase::UniCase;
-use hyper::status::StatusCode;
-use hyper::http::h1::Incoming;
-use hyper::method::Method;
-use hyper::version::HttpVersion;
-use hyper::uri::RequestUri;
-use hyper::buffer::BufReader;
-use hyper::http::h1::parse_request;
-use hyper::header::{Headers, Upgrade, Protocol, ProtocolName, Connection, ConnectionOption};
-
+use handshake::{StatusCode, Incoming, Method, HttpVersion, RequestUri, HttpError, parse_request};
+use buffer::BufReader;
+use header::{Headers, Upgrade, Protocol, ProtocolName, Connection, ConnectionOption};
+#[cfg(feature="ssl")]
+use openssl::ssl::SslStream;
+#[cfg(feature="ssl")]
+use server::tls::ClientCertificate;
+
+#[cfg(feature = "hyper")]
 pub mod from_hyper;
+#[cfg(feature = "http")]
+pub mod from_http;
 
 /// This crate uses buffered readers to read in the handshake quickly, in order to
 /// interface with other use cases that don't use buffered readers the buffered readers
@@ -60,19 +63,21 @@ pub struct WsUpgrade<S>
 	pub request: Request,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: Option<Buffer>,
+	/// Which peers may say who the client behind them is, for the
+	/// `UpgradeRequest` the client keeps.
+	pub trusted_proxies: TrustedProxies,
 }
 
 impl<S> WsUpgrade<S>
     where S: Stream
 {
-	/// Select a protocol to use in the handshake response.
+	/// Select a protocol to use in the handshake response. This replaces
+	/// the one selected before, if any, since a server may only choose one.
+	/// See `server::negotiate` to choose among the ones the client offers.
 	pub fn use_protocol<P>(mut self, protocol: P) -> Self
 		where P: Into<String>
 	{
-		upsert_header!(self.headers; WebSocketProtocol; {
-            Some(protos) => protos.0.push(protocol.into()),
-            None => WebSocketProtocol(vec![protocol.into()])
-        });
+		self.headers.set(WebSocketProtocol(vec![protocol.into()]));
 		self
 	}
 
@@ -98,6 +103,13 @@ impl<S> WsUpgrade<S>
 		self
 	}
 
+	/// Let these peers say, with `Forwarded` or `X-Forwarded-For`, who the
+	/// client behind them is, for `UpgradeRequest::client_addr`.
+	pub fn trust_proxies(mut self, proxies: TrustedProxies) -> Self {
+		self.trusted_proxies = proxies;
+		self
+	}
+
 	/// Accept the handshake request and send a response,
 	/// if nothing goes wrong a client will be created.
 	pub fn accept(self) -> Result<Client<S>, (S, IoError)> {
@@ -108,6 +120,36 @@ impl<S> WsUpgrade<S>
 	/// adding on a few headers. These headers are added before the required
 	/// headers are, so some might be overwritten.
 	pub fn accept_with(mut self, custom_headers: &Headers) -> Result<Client<S>, (S, IoError)> {
+		self.prepare_accept(custom_headers);
+
+		if let Err(e) = self.send(StatusCode::SwitchingProtocols) {
+			return Err((self.stream, e));
+		}
+
+		Ok(self.into_client())
+	}
+
+	/// Accept the handshake request without writing anything to the stream.
+	/// The handshake response is returned as an `http::Response` for the caller
+	/// to send, the client should only be used once it has been sent.
+	///
+	/// This is only available with the `http` feature.
+	#[cfg(feature = "http")]
+	pub fn accept_http(self) -> (::http::Response<()>, Client<S>) {
+		self.accept_http_with(&Headers::new())
+	}
+
+	/// Like `accept_http` while adding on a few headers.
+	/// These headers are added before the required headers are,
+	/// so some might be overwritten.
+	#[cfg(feature = "http")]
+	pub fn accept_http_with(mut self, custom_headers: &Headers) -> (::http::Response<()>, Client<S>) {
+		self.prepare_accept(custom_headers);
+		let response = self.http_response(StatusCode::SwitchingProtocols);
+		(response, self.into_client())
+	}
+
+	fn prepare_accept(&mut self, custom_headers: &Headers) {
 		self.headers.extend(custom_headers.iter());
 		self.headers
 		    .set(WebSocketAccept::new(// NOTE: we know there is a key because this is a valid request
@@ -118,17 +160,19 @@ impl<S> WsUpgrade<S>
 			      ConnectionOption::ConnectionHeader(UniCase("Upgrade".to_string()))
 		    ]));
 		self.headers.set(Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]));
+	}
 
-		if let Err(e) = self.send(StatusCode::SwitchingProtocols) {
-			return Err((self.stream, e));
-		}
-
+	fn into_client(self) -> Client<S> {
 		let stream = match self.buffer {
 			Some(Buffer { buf, pos, cap }) => BufReader::from_parts(self.stream, buf, pos, cap),
 			None => BufReader::new(self.stream),
 		};
 
-		Ok(Client::unchecked(stream, self.headers, false, true))
+		let request = UpgradeRequest {
+			request: self.request,
+			trusted_proxies: self.trusted_proxies,
+		};
+		Client::unchecked(stream, self.headers, false, true).with_request(request)
 	}
 
 	/// Reject the client's request to make a websocket connection.
@@ -137,14 +181,30 @@ impl<S> WsUpgrade<S>
 	}
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
-	pub fn reject_with(mut self, headers: &Headers) -> Result<S, (S, IoError)> {
+	pub fn reject_with(self, headers: &Headers) -> Result<S, (S, IoError)> {
+		self.reject_with_status(StatusCode::BadRequest, headers)
+	}
+
+	/// Reject the client's request to make a websocket connection with
+	/// another status than 400, such as 404 or 403, and send extra headers.
+	pub fn reject_with_status(mut self, status: StatusCode, headers: &Headers) -> Result<S, (S, IoError)> {
 		self.headers.extend(headers.iter());
-		match self.send(StatusCode::BadRequest) {
+		match self.send(status) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
 	}
 
+	/// Reject the client's request without writing anything to the stream,
+	/// returning the rejection as an `http::Response` for the caller to send.
+	///
+	/// This is only available with the `http` feature.
+	#[cfg(feature = "http")]
+	pub fn reject_http(self) -> (::http::Response<()>, S) {
+		let response = self.http_response(StatusCode::BadRequest);
+		(response, self.stream)
+	}
+
 	/// Drop the connection without saying anything.
 	pub fn drop(self) {
 		::std::mem::drop(self);
@@ -183,6 +243,16 @@ impl<S> WsUpgrade<S>
 		self.request.headers.get::<Origin>().map(|o| &o.0 as &str)
 	}
 
+	#[cfg(feature = "http")]
+	fn http_response(&self, status: StatusCode) -> ::http::Response<()> {
+		let mut response = ::http::Response::new(());
+		// every status code we use here is in range
+		*response.status_mut() = ::http::StatusCode::from_u16(status.to_u16()).unwrap();
+		*response.version_mut() = self.request.version.into();
+		*response.headers_mut() = (&self.headers).into();
+		response
+	}
+
 	fn send(&mut self, status: StatusCode) -> IoResult<()> {
 		try!(write!(&mut self.stream, "{} {}\r\n", self.request.version, status));
 		try!(write!(&mut self.stream, "{}\r\n", self.headers));
@@ -200,6 +270,40 @@ impl<S> WsUpgrade<S>
 	}
 }
 
+#[cfg(feature="ssl")]
+impl<S> WsUpgrade<SslStream<S>>
+    where S: Stream
+{
+	/// The certificate the client presented during the TLS handshake, if it
+	/// presented one and it passed verification. Use this to decide if the
+	/// client may connect before calling `accept`.
+	///
+	/// ```rust,no_run
+	/// # use websocket::Server;
+	/// # use websocket::server::tls::SniAcceptorBuilder;
+	/// # let acceptor = SniAcceptorBuilder::new("server.pem", "server.key").build().unwrap();
+	/// let mut server = Server::bind_secure("0.0.0.0:443", acceptor.acceptor()).unwrap();
+	/// let upgrade = server.accept().ok().unwrap();
+	///
+	/// match upgrade.client_certificate() {
+	///     Some(ref cert) if cert.common_name.as_ref().map(|n| &n[..]) == Some("admin") => {
+	///         let client = upgrade.accept().unwrap();
+	///     }
+	///     _ => {
+	///         upgrade.reject().unwrap();
+	///     }
+	/// }
+	/// ```
+	pub fn client_certificate(&self) -> Option<ClientCertificate> {
+		let ssl = self.stream.ssl();
+		if ssl.verify_result().is_some() {
+			return None;
+		}
+		ssl.peer_certificate()
+		   .and_then(|certificate| ClientCertificate::from_x509(&certificate).ok())
+	}
+}
+
 /// Trait to take a stream or similar and attempt to recover the start of a
 /// websocket handshake from it.
 /// Should be used when a stream might contain a request for a websocket session.
@@ -247,7 +351,7 @@ pub trait IntoWs {
 }
 
 
-/// A typical request from hyper
+/// A parsed handshake request.
 pub type Request = Incoming<(Method, RequestUri)>;
 /// If you have your requests separate from your stream you can use this struct
 /// to upgrade the connection based on the request given
@@ -283,6 +387,7 @@ impl<S> IntoWs for S
 				       stream: stream,
 				       request: request,
 				       buffer: buffer,
+				       trusted_proxies: TrustedProxies::None,
 				   })
 			}
 			Err(e) => Err((stream, Some(request), buffer, e)),
@@ -304,6 +409,7 @@ impl<S> IntoWs for RequestStreamPair<S>
 				       stream: self.0,
 				       request: self.1,
 				       buffer: None,
+				       trusted_proxies: TrustedProxies::None,
 				   })
 			}
 			Err(e) => Err((self.0, self.1, e)),
@@ -334,7 +440,7 @@ pub enum HyperIntoWsError {
 	/// IO error from reading the underlying socket
 	Io(io::Error),
 	/// Error while parsing an incoming request
-	Parsing(::hyper::error::Error),
+	Parsing(HttpError),
 }
 
 impl Display for HyperIntoWsError {
@@ -375,8 +481,8 @@ impl From<io::Error> for HyperIntoWsError {
 	}
 }
 
-impl From<::hyper::error::Error> for HyperIntoWsError {
-	fn from(err: ::hyper::error::Error) -> Self {
+impl From<HttpError> for HyperIntoWsError {
+	fn from(err: HttpError) -> Self {
 		HyperIntoWsError::Parsing(err)
 	}
 }
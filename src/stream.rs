@@ -3,10 +3,27 @@
 use std::ops::Deref;
 use std::fmt::Arguments;
 use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 pub use std::net::TcpStream;
 pub use std::net::Shutdown;
 #[cfg(feature="ssl")]
 pub use openssl::ssl::{SslStream, SslContext};
+#[cfg(feature="rustls")]
+pub use rustls::StreamOwned;
+#[cfg(feature="rustls")]
+use rustls::{ClientConnection, ServerConnection, ConnectionCommon, SideData};
+#[cfg(unix)]
+pub use std::os::unix::net::{UnixStream, UnixListener};
+#[cfg(unix)]
+use std::os::unix::net::SocketAddr as UnixSocketAddr;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(windows)]
+use std::os::windows::io::AsRawSocket;
+#[cfg(unix)]
+use libc;
 
 /// Represents a stream that can be read from, and written to.
 /// This is an abstraction around readable and writable things to be able
@@ -22,6 +39,468 @@ pub trait NetworkStream: Read + Write + AsTcpStream {}
 
 impl<S> NetworkStream for S where S: Read + Write + AsTcpStream {}
 
+/// The OS handle of a socket, a file descriptor on Unix.
+#[cfg(unix)]
+pub type RawSocket = ::std::os::unix::io::RawFd;
+/// The OS handle of a socket.
+#[cfg(windows)]
+pub type RawSocket = ::std::os::windows::io::RawSocket;
+
+/// Control over the connection a stream runs on, such as shutting it down,
+/// its addresses, timeouts and blocking mode.
+///
+/// Not every transport can do everything, each method has a default
+/// implementation returning an error of kind `io::ErrorKind::Unsupported`,
+/// so a transport only implements what it supports. An in-memory pipe
+/// could get by with no more than:
+///
+/// ```rust
+/// # use std::io::{self, Read, Write};
+/// use websocket::stream::Transport;
+///
+/// struct Pipe;
+/// # impl Read for Pipe { fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Ok(0) } }
+/// # impl Write for Pipe {
+/// #     fn write(&mut self, b: &[u8]) -> io::Result<usize> { Ok(b.len()) }
+/// #     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+/// # }
+///
+/// impl Transport for Pipe {
+///     type Addr = ();
+/// }
+/// ```
+pub trait Transport {
+	/// The type of address this transport connects, like `SocketAddr` for TCP.
+	type Addr;
+
+	/// Shuts down the read half, write half or both halves of the connection.
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		let _ = how;
+		Err(unsupported("shutdown"))
+	}
+
+	/// The address of the remote end of the connection.
+	fn peer_addr(&self) -> io::Result<Self::Addr> {
+		Err(unsupported("peer_addr"))
+	}
+
+	/// The address of the local end of the connection.
+	fn local_addr(&self) -> io::Result<Self::Addr> {
+		Err(unsupported("local_addr"))
+	}
+
+	/// Sets the `TCP_NODELAY` option, disabling Nagle's algorithm.
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		let _ = nodelay;
+		Err(unsupported("set_nodelay"))
+	}
+
+	/// Sets the timeout for reads, `None` blocks indefinitely.
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		let _ = timeout;
+		Err(unsupported("set_read_timeout"))
+	}
+
+	/// Sets the timeout for writes, `None` blocks indefinitely.
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		let _ = timeout;
+		Err(unsupported("set_write_timeout"))
+	}
+
+	/// Changes whether the transport is in nonblocking mode.
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		let _ = nonblocking;
+		Err(unsupported("set_nonblocking"))
+	}
+
+	/// Creates a new handle to the same connection, this is what allows a
+	/// client to be split into a reader and a writer.
+	fn try_clone(&self) -> io::Result<Self>
+		where Self: Sized
+	{
+		Err(unsupported("try_clone"))
+	}
+
+	/// The OS socket underneath the transport, if there is exactly one.
+	/// A `SharedStream` uses it to wait for the socket to be ready.
+	fn raw_socket(&self) -> Option<RawSocket> {
+		None
+	}
+
+	/// What was negotiated with the peer, for transports running TLS.
+	fn tls_session(&self) -> Option<TlsSession> {
+		None
+	}
+}
+
+/// The outcome of a TLS handshake.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsSession {
+	/// The certificates the peer presented, DER encoded, its own
+	/// certificate first. Empty if the peer presented none.
+	pub peer_certificates: Vec<Vec<u8>>,
+	/// The name of the negotiated cipher suite.
+	pub cipher: String,
+	/// The negotiated protocol version, like `TLSv1.3`.
+	pub protocol: String,
+}
+
+fn unsupported(what: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::Unsupported,
+	               format!("{} is not supported by this transport", what))
+}
+
+impl Transport for TcpStream {
+	type Addr = SocketAddr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		TcpStream::shutdown(self, how)
+	}
+
+	fn peer_addr(&self) -> io::Result<SocketAddr> {
+		TcpStream::peer_addr(self)
+	}
+
+	fn local_addr(&self) -> io::Result<SocketAddr> {
+		TcpStream::local_addr(self)
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		TcpStream::set_nodelay(self, nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		TcpStream::set_read_timeout(self, timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		TcpStream::set_write_timeout(self, timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		TcpStream::set_nonblocking(self, nonblocking)
+	}
+
+	fn try_clone(&self) -> io::Result<TcpStream> {
+		TcpStream::try_clone(self)
+	}
+
+	#[cfg(unix)]
+	fn raw_socket(&self) -> Option<RawSocket> {
+		Some(self.as_raw_fd())
+	}
+
+	#[cfg(windows)]
+	fn raw_socket(&self) -> Option<RawSocket> {
+		Some(self.as_raw_socket())
+	}
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+	type Addr = UnixSocketAddr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		UnixStream::shutdown(self, how)
+	}
+
+	fn peer_addr(&self) -> io::Result<UnixSocketAddr> {
+		UnixStream::peer_addr(self)
+	}
+
+	fn local_addr(&self) -> io::Result<UnixSocketAddr> {
+		UnixStream::local_addr(self)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		UnixStream::set_read_timeout(self, timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		UnixStream::set_write_timeout(self, timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		UnixStream::set_nonblocking(self, nonblocking)
+	}
+
+	fn try_clone(&self) -> io::Result<UnixStream> {
+		UnixStream::try_clone(self)
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		Some(self.as_raw_fd())
+	}
+}
+
+/// TLS state can't be shared between two handles, so an `SslStream`
+/// can't be cloned, everything else is done on the inner stream.
+#[cfg(feature="ssl")]
+impl<S> Transport for SslStream<S>
+	where S: Transport
+{
+	type Addr = S::Addr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.get_ref().shutdown(how)
+	}
+
+	fn peer_addr(&self) -> io::Result<S::Addr> {
+		self.get_ref().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<S::Addr> {
+		self.get_ref().local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.get_ref().set_nodelay(nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.get_ref().set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.get_ref().set_write_timeout(timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.get_ref().set_nonblocking(nonblocking)
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		self.get_ref().raw_socket()
+	}
+
+	fn tls_session(&self) -> Option<TlsSession> {
+		let ssl = self.ssl();
+		let mut certificates = Vec::new();
+		if let Some(chain) = ssl.peer_cert_chain() {
+			for cert in chain {
+				if let Ok(der) = cert.to_der() {
+					certificates.push(der);
+				}
+			}
+		}
+		// servers don't get the client's own certificate in the chain
+		if let Some(Ok(der)) = ssl.peer_certificate().map(|cert| cert.to_der()) {
+			if certificates.first() != Some(&der) {
+				certificates.insert(0, der);
+			}
+		}
+		Some(TlsSession {
+		         peer_certificates: certificates,
+		         cipher: ssl.current_cipher().map(|c| c.name().to_owned()).unwrap_or_default(),
+		         protocol: ssl.version().to_owned(),
+		     })
+	}
+}
+
+/// A TLS stream made by a client using rustls.
+#[cfg(feature="rustls")]
+pub type RustlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A TLS stream accepted by a server using rustls.
+#[cfg(feature="rustls")]
+pub type RustlsServerStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Like an `SslStream` a rustls stream can't be cloned, everything else
+/// is done on the inner stream.
+#[cfg(feature="rustls")]
+impl<C, D, S> Transport for StreamOwned<C, S>
+	where C: Deref<Target = ConnectionCommon<D>>,
+	      D: SideData,
+	      S: Read + Write + Transport
+{
+	type Addr = S::Addr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.sock.shutdown(how)
+	}
+
+	fn peer_addr(&self) -> io::Result<S::Addr> {
+		self.sock.peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<S::Addr> {
+		self.sock.local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.sock.set_nodelay(nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.sock.set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.sock.set_write_timeout(timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.sock.set_nonblocking(nonblocking)
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		self.sock.raw_socket()
+	}
+
+	fn tls_session(&self) -> Option<TlsSession> {
+		let certificates = match self.conn.peer_certificates() {
+			Some(certs) => certs.iter().map(|c| c.as_ref().to_vec()).collect(),
+			None => Vec::new(),
+		};
+		let cipher = match self.conn.negotiated_cipher_suite() {
+			Some(suite) => format!("{:?}", suite.suite()),
+			None => String::new(),
+		};
+		let protocol = match self.conn.protocol_version() {
+			Some(version) => version.as_str().unwrap_or("unknown").replace("v1_", "v1."),
+			None => String::new(),
+		};
+		Some(TlsSession {
+		         peer_certificates: certificates,
+		         cipher: cipher,
+		         protocol: protocol,
+		     })
+	}
+}
+
+/// Reads go to the first stream and writes to the second, the addresses are
+/// those of the reading half.
+impl<R, W> Transport for ReadWritePair<R, W>
+	where R: Read + Transport,
+	      W: Write + Transport<Addr = R::Addr>
+{
+	type Addr = R::Addr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		match how {
+			Shutdown::Read => self.0.shutdown(how),
+			Shutdown::Write => self.1.shutdown(how),
+			Shutdown::Both => {
+				try!(self.0.shutdown(Shutdown::Read));
+				self.1.shutdown(Shutdown::Write)
+			}
+		}
+	}
+
+	fn peer_addr(&self) -> io::Result<R::Addr> {
+		self.0.peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<R::Addr> {
+		self.0.local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.1.set_nodelay(nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.0.set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.1.set_write_timeout(timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		try!(self.0.set_nonblocking(nonblocking));
+		self.1.set_nonblocking(nonblocking)
+	}
+
+	fn try_clone(&self) -> io::Result<Self> {
+		Ok(ReadWritePair(try!(self.0.try_clone()), try!(self.1.try_clone())))
+	}
+}
+
+impl<T> Transport for Box<T>
+	where T: Transport
+{
+	type Addr = T::Addr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.deref().shutdown(how)
+	}
+
+	fn peer_addr(&self) -> io::Result<T::Addr> {
+		self.deref().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<T::Addr> {
+		self.deref().local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.deref().set_nodelay(nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.deref().set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.deref().set_write_timeout(timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.deref().set_nonblocking(nonblocking)
+	}
+
+	fn try_clone(&self) -> io::Result<Box<T>> {
+		self.deref().try_clone().map(Box::new)
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		self.deref().raw_socket()
+	}
+
+	fn tls_session(&self) -> Option<TlsSession> {
+		self.deref().tls_session()
+	}
+}
+
+/// A boxed `NetworkStream` only knows its `TcpStream`, so everything is
+/// done on that, there is no TLS session to report and it can't be cloned.
+impl Transport for Box<NetworkStream> {
+	type Addr = SocketAddr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.as_tcp().shutdown(how)
+	}
+
+	fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.as_tcp().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.as_tcp().local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.as_tcp().set_nodelay(nodelay)
+	}
+
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.as_tcp().set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.as_tcp().set_write_timeout(timeout)
+	}
+
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.as_tcp().set_nonblocking(nonblocking)
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		self.as_tcp().raw_socket()
+	}
+}
+
 /// some streams can be split up into separate reading and writing components
 /// `TcpStream` is an example. This trait marks this ability so one can split
 /// up the client into two parts.
@@ -58,8 +537,191 @@ impl Splittable for TcpStream {
 	}
 }
 
+/// A stream shared between threads, so that one thread can read from it
+/// while others write to it. This is what lets streams that can't be cloned,
+/// like TLS streams, be split into a reader and a writer.
+///
+/// The stream is put in nonblocking mode and kept behind a lock that is only
+/// held while a read or write is actually making progress. When the stream
+/// would block the lock is let go and the handle waits on the socket, so a
+/// reader waiting for data never holds up a writer.
+pub struct SharedStream<S> {
+	shared: Arc<Shared<S>>,
+	socket: Option<RawSocket>,
+}
+
+struct Shared<S> {
+	stream: Mutex<S>,
+	write_order: Mutex<()>,
+}
+
+/// How long a handle waits on the socket before trying again, a would-be
+/// blocking TLS read can be waiting on a write and the other way around.
+const SHARED_WAIT_MS: i32 = 50;
+
+impl<S> SharedStream<S>
+	where S: Transport
+{
+	/// Puts the stream in nonblocking mode and wraps it up to be shared.
+	pub fn new(stream: S) -> io::Result<SharedStream<S>> {
+		try!(stream.set_nonblocking(true));
+		let socket = stream.raw_socket();
+		Ok(SharedStream {
+		       shared: Arc::new(Shared {
+		                            stream: Mutex::new(stream),
+		                            write_order: Mutex::new(()),
+		                        }),
+		       socket: socket,
+		   })
+	}
+
+	/// Writes all of `buf` and flushes it, with no writes through other
+	/// handles in between. Used to write whole messages, which a TLS stream
+	/// could otherwise keep buffered.
+	pub fn write_all_exclusive(&self, buf: &[u8]) -> io::Result<()>
+		where S: Write
+	{
+		let _order = lock(&self.shared.write_order);
+		let mut handle = self.clone();
+		try!(handle.write_all(buf));
+		handle.flush()
+	}
+
+	/// Locks the stream for direct access, reads and writes through
+	/// other handles wait until the guard is dropped.
+	pub fn lock<'a>(&'a self) -> MutexGuard<'a, S> {
+		lock(&self.shared.stream)
+	}
+
+	fn wait(&self, write: bool) {
+		wait_ready(self.socket, write)
+	}
+}
+
+impl<S> Clone for SharedStream<S> {
+	fn clone(&self) -> SharedStream<S> {
+		SharedStream {
+			shared: self.shared.clone(),
+			socket: self.socket,
+		}
+	}
+}
+
+impl<S> Read for SharedStream<S>
+	where S: Read + Transport
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let res = self.lock().read(buf);
+			match res {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.wait(false),
+				res => return res,
+			}
+		}
+	}
+}
+
+impl<S> Write for SharedStream<S>
+	where S: Write + Transport
+{
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		loop {
+			let res = self.lock().write(buf);
+			match res {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.wait(true),
+				res => return res,
+			}
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		loop {
+			let res = self.lock().flush();
+			match res {
+				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => self.wait(true),
+				res => return res,
+			}
+		}
+	}
+}
+
+/// The stream has to stay nonblocking and have no timeouts to be shared,
+/// so those can't be changed.
+impl<S> Transport for SharedStream<S>
+	where S: Transport
+{
+	type Addr = S::Addr;
+
+	fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.lock().shutdown(how)
+	}
+
+	fn peer_addr(&self) -> io::Result<S::Addr> {
+		self.lock().peer_addr()
+	}
+
+	fn local_addr(&self) -> io::Result<S::Addr> {
+		self.lock().local_addr()
+	}
+
+	fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.lock().set_nodelay(nodelay)
+	}
+
+	fn try_clone(&self) -> io::Result<SharedStream<S>> {
+		Ok(self.clone())
+	}
+
+	fn raw_socket(&self) -> Option<RawSocket> {
+		self.socket
+	}
+
+	fn tls_session(&self) -> Option<TlsSession> {
+		self.lock().tls_session()
+	}
+}
+
+fn lock<'a, T>(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+	// a panic while holding the lock doesn't leave the stream in a worse
+	// state than an io error would
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(unix)]
+fn wait_ready(socket: Option<RawSocket>, write: bool) {
+	let fd = match socket {
+		Some(fd) => fd,
+		None => return ::std::thread::sleep(Duration::from_millis(1)),
+	};
+	let mut pollfd = libc::pollfd {
+		fd: fd,
+		events: if write { libc::POLLOUT } else { libc::POLLIN },
+		revents: 0,
+	};
+	// errors and hangups show up on the next read or write
+	unsafe {
+		libc::poll(&mut pollfd, 1, SHARED_WAIT_MS);
+	}
+}
+
+#[cfg(not(unix))]
+fn wait_ready(_socket: Option<RawSocket>, _write: bool) {
+	::std::thread::sleep(Duration::from_millis(1));
+}
+
+#[cfg(unix)]
+impl Splittable for UnixStream {
+	type Reader = UnixStream;
+	type Writer = UnixStream;
+
+	fn split(self) -> io::Result<(UnixStream, UnixStream)> {
+		self.try_clone().map(|s| (s, self))
+	}
+}
+
 /// The ability access a borrow to an underlying TcpStream,
 /// so one can set options on the stream such as `nonblocking`.
+/// Controls that work on any kind of connection are in `Transport`.
 pub trait AsTcpStream {
 	/// Get a borrow of the TcpStream
 	fn as_tcp(&self) -> &TcpStream;
@@ -78,6 +740,13 @@ impl AsTcpStream for SslStream<TcpStream> {
 	}
 }
 
+#[cfg(feature="rustls")]
+impl<C> AsTcpStream for StreamOwned<C, TcpStream> {
+	fn as_tcp(&self) -> &TcpStream {
+		&self.sock
+	}
+}
+
 impl<T> AsTcpStream for Box<T>
     where T: AsTcpStream
 {
@@ -137,3 +806,106 @@ impl<R, W> Write for ReadWritePair<R, W>
 		self.1.write_fmt(fmt)
 	}
 }
+
+/// The credentials of the process on the other end of a Unix domain socket,
+/// taken when the connection was made.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UCred {
+	/// The user ID of the peer process.
+	pub uid: u32,
+	/// The group ID of the peer process.
+	pub gid: u32,
+	/// The process ID of the peer, on platforms that report it.
+	pub pid: Option<i32>,
+}
+
+/// Get the credentials of the process on the other end of a Unix domain socket.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn peer_cred(stream: &UnixStream) -> io::Result<UCred> {
+	use std::mem;
+
+	let mut cred: libc::ucred = unsafe { mem::zeroed() };
+	let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+	let ret = unsafe {
+		libc::getsockopt(stream.as_raw_fd(),
+		                 libc::SOL_SOCKET,
+		                 libc::SO_PEERCRED,
+		                 &mut cred as *mut libc::ucred as *mut libc::c_void,
+		                 &mut len)
+	};
+	if ret != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(UCred {
+	       uid: cred.uid,
+	       gid: cred.gid,
+	       pid: Some(cred.pid),
+	   })
+}
+
+/// Get the credentials of the process on the other end of a Unix domain socket.
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+pub fn peer_cred(stream: &UnixStream) -> io::Result<UCred> {
+	let mut uid = 0;
+	let mut gid = 0;
+	if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(UCred {
+	       uid: uid,
+	       gid: gid,
+	       pid: None,
+	   })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use std::net::TcpListener;
+
+	struct Pipe(Cursor<Vec<u8>>);
+
+	impl Read for Pipe {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.0.read(buf)
+		}
+	}
+
+	impl Write for Pipe {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl Transport for Pipe {
+		type Addr = ();
+	}
+
+	#[test]
+	fn missing_capabilities_are_unsupported() {
+		let pipe = ReadWritePair(Pipe(Cursor::new(Vec::new())), Pipe(Cursor::new(Vec::new())));
+		let err = pipe.shutdown(Shutdown::Both).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+		assert!(pipe.try_clone().is_err());
+	}
+
+	#[test]
+	fn boxed_tcp_transport() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let stream: Box<NetworkStream> = Box::new(TcpStream::connect(addr).unwrap());
+
+		assert_eq!(stream.peer_addr().unwrap(), addr);
+		stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+		stream.shutdown(Shutdown::Both).unwrap();
+
+		let stream = Box::new(TcpStream::connect(addr).unwrap());
+		assert_eq!(stream.try_clone().unwrap().peer_addr().unwrap(), addr);
+	}
+}